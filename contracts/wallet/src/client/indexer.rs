@@ -1,5 +1,3 @@
-use std::str;
-
 use anyhow::{anyhow, Context, Result};
 use client_sdk::{
     contract_indexer::{
@@ -18,17 +16,147 @@ use client_sdk::{
 use sdk::Hashed;
 use serde::Serialize;
 
+use super::tx_executor_handler::WalletProvableState;
 use crate::*;
 use client_sdk::contract_indexer::axum;
 use client_sdk::contract_indexer::utoipa;
 
-#[derive(Debug, Clone, Default, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct WalletEvent {
     pub account: sdk::Identity,
-    pub program_outputs: String,
+    pub outcome: WalletOutcome,
+    /// Set when the action changed the security of an account, which may not be the sender's
+    pub notification: Option<WalletNotification>,
+}
+
+/// What happened to a wallet transaction
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "status")]
+pub enum WalletOutcome {
+    /// Executed by the contract
+    Success {
+        output: WalletOutput,
+    },
+    /// Rejected by the contract
+    Error {
+        code: u16,
+        message: String,
+        error: WalletError,
+    },
+    /// Settled as failed, e.g. because another blob of the transaction failed
+    Failed,
+    TimedOut,
+}
+
+impl WalletOutcome {
+    /// Outcome of the contract's execution of a transaction
+    /// Outputs that cannot be decoded, e.g. from another version of the contract, are reported as failed.
+    pub fn from_hyle_output(hyle_output: &sdk::HyleOutput) -> Self {
+        if hyle_output.success {
+            return match WalletOutput::from_program_outputs(&hyle_output.program_outputs) {
+                Some(output) => WalletOutcome::Success { output },
+                None => WalletOutcome::Failed,
+            };
+        }
+        let error =
+            WalletError::from_program_outputs(&hyle_output.program_outputs).unwrap_or_else(|| {
+                WalletError::Sdk(String::from_utf8_lossy(&hyle_output.program_outputs).into_owned())
+            });
+        WalletOutcome::Error {
+            code: error.code(),
+            message: error.to_string(),
+            error,
+        }
+    }
+}
+
+impl std::fmt::Display for WalletOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalletOutcome::Success { output } => write!(f, "{output:?}"),
+            WalletOutcome::Error { code, message, .. } => write!(f, "Error {code}: {message}"),
+            WalletOutcome::Failed => write!(f, "Transaction failed"),
+            WalletOutcome::TimedOut => write!(f, "Transaction timeout"),
+        }
+    }
+}
+
+/// Changes to an account that its owner must hear about, e.g. to log other sessions out
+#[derive(Debug, Clone, Serialize)]
+pub enum WalletNotification {
+    PasswordChanged {
+        account: sdk::Identity,
+    },
+    RecoveryPending {
+        account: sdk::Identity,
+        executable_at: u128,
+    },
+    RecoveryCancelled {
+        account: sdk::Identity,
+    },
+    AccountRecovered {
+        account: sdk::Identity,
+    },
 }
 
-impl Wallet {
+impl WalletNotification {
+    pub fn account(&self) -> &sdk::Identity {
+        match self {
+            WalletNotification::PasswordChanged { account }
+            | WalletNotification::RecoveryPending { account, .. }
+            | WalletNotification::RecoveryCancelled { account }
+            | WalletNotification::AccountRecovered { account } => account,
+        }
+    }
+}
+
+impl WalletProvableState {
+    /// Notification for a successfully executed `action`, built from the updated state
+    fn notification(
+        &self,
+        action: &WalletAction,
+        contract_name: &sdk::ContractName,
+    ) -> Option<WalletNotification> {
+        let identity = |account: &str| sdk::Identity(format!("{account}@{}", contract_name.0));
+        match action {
+            WalletAction::RotatePassword { account, .. } => {
+                Some(WalletNotification::PasswordChanged {
+                    account: identity(account),
+                })
+            }
+            WalletAction::ApproveRecovery { account, .. } => {
+                let pending = self.identities.get(account)?.pending_recovery.as_ref()?;
+                Some(WalletNotification::RecoveryPending {
+                    account: identity(account),
+                    executable_at: pending.executable_at.as_ref()?.0,
+                })
+            }
+            WalletAction::InitiateKeyRecovery { account, .. } => {
+                let pending = self
+                    .identities
+                    .get(account)?
+                    .pending_key_recovery
+                    .as_ref()?;
+                Some(WalletNotification::RecoveryPending {
+                    account: identity(account),
+                    executable_at: pending.executable_at.0,
+                })
+            }
+            WalletAction::CancelRecovery { account } => {
+                Some(WalletNotification::RecoveryCancelled {
+                    account: identity(account),
+                })
+            }
+            WalletAction::FinalizeRecovery { account, .. }
+            | WalletAction::FinalizeKeyRecovery { account, .. } => {
+                Some(WalletNotification::AccountRecovered {
+                    account: identity(account),
+                })
+            }
+            _ => None,
+        }
+    }
+
     fn handle_transaction(
         &mut self,
         tx: &sdk::BlobTransaction,
@@ -37,8 +165,9 @@ impl Wallet {
     ) -> Result<Option<WalletEvent>> {
         let sdk::Blob {
             contract_name,
-            data: _,
+            data,
         } = tx.blobs.get(index.0).context("Failed to get blob")?;
+        let action = borsh::from_slice::<WalletAction>(&data.0).ok();
 
         let calldata = sdk::Calldata {
             identity: tx.identity.clone(),
@@ -50,40 +179,42 @@ impl Wallet {
             private_input: vec![],
         };
 
-        let res = self.handle(&calldata);
-        let event = match res {
-            Ok(hyle_output) => {
-                let program_outputs =
-                    str::from_utf8(&hyle_output.program_outputs).unwrap_or("no output");
-
-                sdk::info!("🚀 Executed {contract_name}: {}", program_outputs);
-                sdk::tracing::debug!(
-                    handler = %contract_name,
-                    "hyle_output: {:?}", hyle_output
-                );
-                WalletEvent {
-                    account: tx.identity.clone(),
-                    program_outputs: program_outputs.to_string(),
-                }
-            }
+        let hyle_output = match self.handle(&calldata) {
+            Ok(hyle_output) => hyle_output,
             Err(e) => {
                 sdk::info!("🚀 Executed {contract_name} with error: {}", e);
-                WalletEvent {
+                return Ok(Some(WalletEvent {
                     account: tx.identity.clone(),
-                    program_outputs: format!("Error: {:?}", e),
-                }
+                    outcome: WalletOutcome::Failed,
+                    notification: None,
+                }));
             }
         };
+        let outcome = WalletOutcome::from_hyle_output(&hyle_output);
+
+        sdk::info!("🚀 Executed {contract_name}: {}", outcome);
+        sdk::tracing::debug!(
+            handler = %contract_name,
+            "hyle_output: {:?}", hyle_output
+        );
+        let notification = action
+            .filter(|_| hyle_output.success)
+            .and_then(|action| self.notification(&action, contract_name));
 
-        Ok(Some(event))
+        Ok(Some(WalletEvent {
+            account: tx.identity.clone(),
+            outcome,
+            notification,
+        }))
     }
 }
 
-impl ContractHandler<WalletEvent> for Wallet {
-    async fn api(store: ContractHandlerStore<Wallet>) -> (Router<()>, OpenApi) {
+impl ContractHandler<WalletEvent> for WalletProvableState {
+    async fn api(store: ContractHandlerStore<WalletProvableState>) -> (Router<()>, OpenApi) {
         let (router, api) = OpenApiRouter::default()
             .routes(routes!(get_state))
             .routes(routes!(get_account_info))
+            .routes(routes!(get_pending_recoveries))
             .split_for_parts();
 
         (router.with_state(store), api)
@@ -106,7 +237,8 @@ impl ContractHandler<WalletEvent> for Wallet {
     ) -> Result<Option<WalletEvent>> {
         Ok(Some(WalletEvent {
             account: tx.identity.clone(),
-            program_outputs: "Transaction failed".to_string(),
+            outcome: WalletOutcome::Failed,
+            notification: None,
         }))
     }
 
@@ -118,7 +250,8 @@ impl ContractHandler<WalletEvent> for Wallet {
     ) -> Result<Option<WalletEvent>> {
         Ok(Some(WalletEvent {
             account: tx.identity.clone(),
-            program_outputs: "Transaction timeout".to_string(),
+            outcome: WalletOutcome::TimedOut,
+            notification: None,
         }))
     }
 }
@@ -131,14 +264,17 @@ impl ContractHandler<WalletEvent> for Wallet {
         (status = OK, description = "Get json state of contract")
     )
 )]
-pub async fn get_state<S: Serialize + Clone + 'static>(
+pub async fn get_state<S: Serialize + 'static>(
     State(state): State<ContractHandlerStore<S>>,
 ) -> Result<impl IntoResponse, AppError> {
     let store = state.read().await;
-    store.state.clone().map(Json).ok_or(AppError(
+    let state = store.state.as_ref().ok_or(AppError(
         StatusCode::NOT_FOUND,
         anyhow!("No state found for contract '{}'", store.contract_name),
-    ))
+    ))?;
+    let state = serde_json::to_value(state)
+        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, anyhow!(e)))?;
+    Ok(Json(state))
 }
 
 #[derive(Serialize, ToSchema)]
@@ -146,14 +282,21 @@ struct SessionKey {
     key: String,
     expiration_date: u128,
     nonce: u128,
+    whitelist: Option<Vec<sdk::ContractName>>,
+    permissions: Vec<Permission>,
+    spending_limits: Vec<SpendingLimit>,
 }
 
 #[derive(Serialize, ToSchema)]
 struct AccountInfo {
     account: String,
-    auth_method: AuthMethod,
+    auth_methods: BTreeMap<String, AuthMethod>,
     session_keys: Vec<SessionKey>,
     nonce: u128,
+    guardians: Option<Guardians>,
+    pending_recovery: Option<PendingRecovery>,
+    recovery_key: Option<RecoveryKey>,
+    pending_key_recovery: Option<PendingKeyRecovery>,
 }
 
 #[utoipa::path(
@@ -170,10 +313,10 @@ struct AccountInfo {
 )]
 pub async fn get_account_info(
     Path(account): Path<String>,
-    State(state): State<ContractHandlerStore<Wallet>>,
+    State(state): State<ContractHandlerStore<WalletProvableState>>,
 ) -> Result<impl IntoResponse, AppError> {
     let store = state.read().await;
-    let state = store.state.clone().ok_or(AppError(
+    let state = store.state.as_ref().ok_or(AppError(
         StatusCode::NOT_FOUND,
         anyhow!("Contract '{}' not found", store.contract_name),
     ))?;
@@ -190,13 +333,58 @@ pub async fn get_account_info(
             key: sk.public_key.clone(),
             expiration_date: sk.expiration_date.0,
             nonce: sk.nonce,
+            whitelist: sk.whitelist.clone(),
+            permissions: sk.permissions.clone(),
+            spending_limits: sk.spending_limits.clone(),
         })
         .collect();
 
     Ok(Json(AccountInfo {
         account,
-        auth_method: account_info.auth_method.clone(),
+        auth_methods: account_info.auth_methods.clone(),
         session_keys,
         nonce: account_info.nonce,
+        guardians: account_info.guardians.clone(),
+        pending_recovery: account_info.pending_recovery.clone(),
+        recovery_key: account_info.recovery_key.clone(),
+        pending_key_recovery: account_info.pending_key_recovery.clone(),
     }))
 }
+
+#[derive(Serialize, ToSchema)]
+struct PendingRecoveries {
+    account: String,
+    pending_recovery: Option<PendingRecovery>,
+    pending_key_recovery: Option<PendingKeyRecovery>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/recoveries",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Get the accounts with a pending recovery", body = Vec<PendingRecoveries>)
+    )
+)]
+pub async fn get_pending_recoveries(
+    State(state): State<ContractHandlerStore<WalletProvableState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let state = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+
+    let recoveries: Vec<PendingRecoveries> = state
+        .identities
+        .iter()
+        .filter(|(_, info)| info.pending_recovery.is_some() || info.pending_key_recovery.is_some())
+        .map(|(account, info)| PendingRecoveries {
+            account: account.clone(),
+            pending_recovery: info.pending_recovery.clone(),
+            pending_key_recovery: info.pending_key_recovery.clone(),
+        })
+        .collect();
+
+    Ok(Json(recoveries))
+}
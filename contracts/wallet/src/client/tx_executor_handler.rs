@@ -1,18 +1,151 @@
-use anyhow::Context;
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Context};
+use borsh::{BorshDeserialize, BorshSerialize};
 use client_sdk::transaction_builder::TxExecutorHandler;
 use sdk::{utils::as_hyle_output, Blob, Calldata, RegisterContractEffect, ZkContract};
+use serde::Serialize;
+use sparse_merkle_tree::{default_store::DefaultStore, SparseMerkleTree, H256};
+
+use crate::{
+    migration::{v0, VersionedAccount, VersionedState},
+    smt::{account_key, account_value, PartialState, SHA256Hasher},
+    AccountInfo, Wallet, WalletAction, WalletError,
+};
+
+type AccountsTree = SparseMerkleTree<SHA256Hasher, H256, DefaultStore<H256>>;
+
+/// Account proven for the transactions that touch none
+const UNTOUCHED_ACCOUNT: &str = "";
+
+/// Full off-chain state of the wallet: the accounts, and the merkle tree whose root is
+/// committed on-chain. It builds the partial states the contract executes transactions on.
+#[derive(Default)]
+pub struct WalletProvableState {
+    tree: AccountsTree,
+    /// Accounts, migrated to the latest version
+    pub identities: BTreeMap<String, AccountInfo>,
+    /// Accounts whose leaf is still in an older version, until a transaction migrates them
+    legacy: BTreeMap<String, VersionedAccount>,
+}
+
+impl WalletProvableState {
+    pub fn get_nonce(&self, username: &str) -> Result<u128, WalletError> {
+        let info = self
+            .identities
+            .get(username)
+            .ok_or(WalletError::IdentityNotFound)?;
+        Ok(info.nonce)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        (*self.tree.root()).into()
+    }
+
+    /// The account as stored in its leaf
+    fn leaf(&self, account: &str) -> Option<VersionedAccount> {
+        match self.legacy.get(account) {
+            Some(leaf) => Some(leaf.clone()),
+            None => self
+                .identities
+                .get(account)
+                .cloned()
+                .map(VersionedAccount::from),
+        }
+    }
 
-use crate::Wallet;
+    fn update(&mut self, account: &str, leaf: Option<VersionedAccount>) -> anyhow::Result<()> {
+        self.tree
+            .update(account_key(account), account_value(leaf.as_ref()))
+            .map_err(|e| anyhow!("Failed to update account {account}: {e:?}"))?;
+        self.legacy.remove(account);
+        match leaf {
+            Some(leaf) => {
+                if !leaf.is_latest() {
+                    self.legacy.insert(account.to_string(), leaf.clone());
+                }
+                self.identities.insert(account.to_string(), leaf.migrate());
+            }
+            None => {
+                self.identities.remove(account);
+            }
+        }
+        Ok(())
+    }
+
+    /// The accounts touched by `blob`, proven against the current root.
+    /// A blob that is not a wallet action touches no account, and will fail to execute: the
+    /// account named [`UNTOUCHED_ACCOUNT`] is proven instead, as a merkle proof needs a key.
+    fn partial_state(&self, blob: &Blob) -> anyhow::Result<PartialState> {
+        let touched: Vec<String> = borsh::from_slice::<WalletAction>(&blob.data.0)
+            .map(|action| {
+                action
+                    .touched_accounts()
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_else(|_| vec![UNTOUCHED_ACCOUNT.to_string()]);
+        let accounts: BTreeMap<String, Option<VersionedAccount>> = touched
+            .into_iter()
+            .map(|account| {
+                let leaf = self.leaf(&account);
+                (account, leaf)
+            })
+            .collect();
+
+        let keys: Vec<H256> = accounts
+            .keys()
+            .map(|account| account_key(account))
+            .collect();
+        let proof = self
+            .tree
+            .merkle_proof(keys.clone())
+            .and_then(|proof| proof.compile(keys))
+            .map_err(|e| anyhow!("Failed to build merkle proof: {e:?}"))?;
+
+        Ok(PartialState {
+            accounts,
+            proof: proof.0,
+        })
+    }
+}
 
-impl TxExecutorHandler for Wallet {
-    fn build_commitment_metadata(&self, _blob: &Blob) -> anyhow::Result<Vec<u8>> {
-        borsh::to_vec(self).context("Failed to serialize Wallet")
+impl TxExecutorHandler for WalletProvableState {
+    fn build_commitment_metadata(&self, blob: &Blob) -> anyhow::Result<Vec<u8>> {
+        let wallet = Wallet::with_partial_states(self.root(), vec![self.partial_state(blob)?]);
+        borsh::to_vec(&wallet).context("Failed to serialize Wallet")
+    }
+
+    fn merge_commitment_metadata(
+        &self,
+        initial: Vec<u8>,
+        next: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let mut wallet = borsh::from_slice::<Wallet>(&initial).map_err(|e| e.to_string())?;
+        wallet.merge(borsh::from_slice::<Wallet>(&next).map_err(|e| e.to_string())?);
+        borsh::to_vec(&wallet).map_err(|e| e.to_string())
     }
 
     fn handle(&mut self, calldata: &Calldata) -> anyhow::Result<sdk::HyleOutput> {
-        let initial_state_commitment = <Self as ZkContract>::commit(self);
-        let mut res = <Self as ZkContract>::execute(self, calldata);
-        let next_state_commitment = <Self as ZkContract>::commit(self);
+        let blob = calldata
+            .blobs
+            .iter()
+            .find(|(index, _)| index == &calldata.index)
+            .map(|(_, blob)| blob)
+            .context("Missing wallet blob")?;
+        let mut wallet = Wallet::with_partial_states(self.root(), vec![self.partial_state(blob)?]);
+
+        let initial_state_commitment = <Wallet as ZkContract>::commit(&wallet);
+        let mut res = <Wallet as ZkContract>::execute(&mut wallet, calldata);
+        let next_state_commitment = <Wallet as ZkContract>::commit(&wallet);
+
+        if res.is_ok() {
+            // The contract writes the touched accounts in the latest version
+            for (account, info) in wallet.touched_accounts() {
+                self.update(account, info.cloned().map(VersionedAccount::from))?;
+            }
+        }
         Ok(as_hyle_output(
             initial_state_commitment,
             next_state_commitment,
@@ -21,10 +154,106 @@ impl TxExecutorHandler for Wallet {
         ))
     }
 
+    /// `metadata` is a snapshot of the accounts of any version, see [`VersionedState`]
     fn construct_state(
         _register_blob: &RegisterContractEffect,
-        _metadata: &Option<Vec<u8>>,
+        metadata: &Option<Vec<u8>>,
     ) -> anyhow::Result<Self> {
-        Ok(Self::default())
+        match metadata {
+            Some(metadata) => VersionedState::decode(metadata)
+                .context("Failed to decode wallet state")?
+                .try_into(),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+impl WalletProvableState {
+    /// State to register when upgrading from the on-chain `state_commitment` of a previous program.
+    /// The first wallet committed its whole Borsh-encoded state, which is never 32 bytes long:
+    /// its accounts become leaves of the tree, migrated when a transaction touches them.
+    /// Later ones commit the root of the tree: the state is then the `indexed` one, which must
+    /// have that root.
+    pub fn upgraded(
+        state_commitment: &[u8],
+        indexed: impl FnOnce() -> anyhow::Result<Self>,
+    ) -> anyhow::Result<Self> {
+        if state_commitment.len() == 32 {
+            let state = indexed()?;
+            if state.root() != state_commitment {
+                bail!(
+                    "Indexed wallet state has root {}, but the on-chain one is {}",
+                    hex::encode(state.root()),
+                    hex::encode(state_commitment)
+                );
+            }
+            return Ok(state);
+        }
+        let accounts = borsh::from_slice::<BTreeMap<String, v0::AccountInfo>>(state_commitment)
+            .context("Failed to decode the state of the first wallet")?;
+        Self::try_from(VersionedState::V0(accounts))
+    }
+
+    /// Snapshot of the accounts as stored in their leaves
+    pub fn snapshot(&self) -> VersionedState {
+        VersionedState::V1(
+            self.identities
+                .keys()
+                .filter_map(|account| Some((account.clone(), self.leaf(account)?)))
+                .collect(),
+        )
+    }
+}
+
+/// Only the accounts are stored, in a versioned snapshot. The tree is rebuilt from them.
+impl BorshSerialize for WalletProvableState {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        writer.write_all(&self.snapshot().encode()?)
+    }
+}
+
+/// Decodes snapshots of any version, see [`VersionedState::decode_reader`]
+impl BorshDeserialize for WalletProvableState {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        Self::try_from(VersionedState::decode_reader(reader)?)
+            .map_err(|e| borsh::io::Error::other(e.to_string()))
+    }
+}
+
+impl TryFrom<VersionedState> for WalletProvableState {
+    type Error = anyhow::Error;
+
+    fn try_from(snapshot: VersionedState) -> anyhow::Result<Self> {
+        let mut state = Self::default();
+        for (account, leaf) in snapshot.into_accounts() {
+            state.update(&account, Some(leaf))?;
+        }
+        Ok(state)
+    }
+}
+
+/// Copies the nodes of the tree, without hashing anything again
+impl Clone for WalletProvableState {
+    fn clone(&self) -> Self {
+        Self {
+            tree: AccountsTree::new(*self.tree.root(), self.tree.store().clone()),
+            identities: self.identities.clone(),
+            legacy: self.legacy.clone(),
+        }
+    }
+}
+
+impl std::fmt::Debug for WalletProvableState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalletProvableState")
+            .field("root", &hex::encode(self.root()))
+            .field("identities", &self.identities)
+            .finish()
+    }
+}
+
+impl Serialize for WalletProvableState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialize::serialize(&self.identities, serializer)
     }
 }
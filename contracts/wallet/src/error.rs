@@ -0,0 +1,262 @@
+use std::fmt;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sdk::{ContractName, Identity};
+use serde::{Deserialize, Serialize};
+
+use crate::TokenActionKind;
+
+/// Errors of the wallet contract.
+/// The program outputs of a failed transaction are its error, Borsh encoded then hex encoded
+/// (failure outputs must be valid UTF-8).
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub enum WalletError {
+    /// Reported by the SDK, e.g. an undecodable action or an invalid signature blob
+    Sdk(String),
+    InvalidIdentity {
+        expected: String,
+        got: String,
+    },
+    MissingTxContext,
+    /// Some blobs of the transaction are not in the calldata
+    IncompleteCalldata,
+    MissingBlob(String),
+    InvalidBlob(String),
+    InvalidAction,
+    IdentityNotFound,
+    IdentityAlreadyExists,
+    InvalidNonce,
+    NoAuthMethod,
+    InvalidAuthentication,
+    AuthMethodAlreadyExists,
+    AuthMethodNotFound,
+    LastAuthMethod,
+    InvalidPublicKey,
+    InvalidSignedMessage,
+    /// The signature blob was made for another identity
+    SignerIdentityMismatch,
+    InvalidAuthenticatorData,
+    UserPresenceNotAsserted,
+    InvalidClientData,
+    InvalidClientDataType,
+    InvalidChallenge,
+    InvalidRelyingParty,
+    InvalidOrigin,
+    InvalidWebAuthnSignature,
+    SessionKeyAlreadyExists,
+    SessionKeyNotFound,
+    SessionKeyExpired,
+    SessionKeyNonceAlreadyUsed,
+    NotWhitelisted(ContractName),
+    WrongLane,
+    NotATokenAction(ContractName),
+    ActionNotAllowed {
+        contract_name: ContractName,
+        action: TokenActionKind,
+    },
+    RecipientNotAllowed {
+        contract_name: ContractName,
+        recipient: Identity,
+    },
+    TransactionLimitExceeded(ContractName),
+    WindowLimitExceeded(ContractName),
+    GuardianNotFound,
+    SelfGuardian,
+    DuplicateGuardian,
+    InvalidGuardianThreshold,
+    NoGuardians,
+    NotAGuardian,
+    RecoveryPending,
+    RecoveryAlreadyApproved,
+    RecoveryThresholdNotReached,
+    RecoveryDelayNotExpired,
+    NoPendingRecovery,
+    NoRecoveryKey,
+    InvalidRecoveryKey,
+    MissingCommitmentMetadata,
+    InvalidCommitmentMetadata,
+    /// An account of the action is not in the commitment metadata
+    AccountNotProven(String),
+}
+
+impl WalletError {
+    /// Stable code of the error, grouped by domain:
+    /// 1xx transaction, 2xx account and authentication, 3xx session keys, 4xx recovery
+    pub fn code(&self) -> u16 {
+        match self {
+            WalletError::Sdk(_) => 100,
+            WalletError::InvalidIdentity { .. } => 101,
+            WalletError::MissingTxContext => 102,
+            WalletError::IncompleteCalldata => 103,
+            WalletError::MissingBlob(_) => 104,
+            WalletError::InvalidBlob(_) => 105,
+            WalletError::InvalidAction => 106,
+            WalletError::MissingCommitmentMetadata => 107,
+            WalletError::InvalidCommitmentMetadata => 108,
+            WalletError::AccountNotProven(_) => 109,
+            WalletError::IdentityNotFound => 200,
+            WalletError::IdentityAlreadyExists => 201,
+            WalletError::InvalidNonce => 202,
+            WalletError::NoAuthMethod => 203,
+            WalletError::InvalidAuthentication => 204,
+            WalletError::AuthMethodAlreadyExists => 205,
+            WalletError::AuthMethodNotFound => 206,
+            WalletError::LastAuthMethod => 207,
+            WalletError::InvalidPublicKey => 208,
+            WalletError::InvalidSignedMessage => 209,
+            WalletError::SignerIdentityMismatch => 210,
+            WalletError::InvalidAuthenticatorData => 211,
+            WalletError::UserPresenceNotAsserted => 212,
+            WalletError::InvalidClientData => 213,
+            WalletError::InvalidClientDataType => 214,
+            WalletError::InvalidChallenge => 215,
+            WalletError::InvalidRelyingParty => 216,
+            WalletError::InvalidOrigin => 217,
+            WalletError::InvalidWebAuthnSignature => 218,
+            WalletError::SessionKeyAlreadyExists => 300,
+            WalletError::SessionKeyNotFound => 301,
+            WalletError::SessionKeyExpired => 302,
+            WalletError::SessionKeyNonceAlreadyUsed => 303,
+            WalletError::NotWhitelisted(_) => 304,
+            WalletError::WrongLane => 305,
+            WalletError::NotATokenAction(_) => 306,
+            WalletError::ActionNotAllowed { .. } => 307,
+            WalletError::RecipientNotAllowed { .. } => 308,
+            WalletError::TransactionLimitExceeded(_) => 309,
+            WalletError::WindowLimitExceeded(_) => 310,
+            WalletError::GuardianNotFound => 400,
+            WalletError::SelfGuardian => 401,
+            WalletError::DuplicateGuardian => 402,
+            WalletError::InvalidGuardianThreshold => 403,
+            WalletError::NoGuardians => 404,
+            WalletError::NotAGuardian => 405,
+            WalletError::RecoveryPending => 406,
+            WalletError::RecoveryAlreadyApproved => 407,
+            WalletError::RecoveryThresholdNotReached => 408,
+            WalletError::RecoveryDelayNotExpired => 409,
+            WalletError::NoPendingRecovery => 410,
+            WalletError::NoRecoveryKey => 411,
+            WalletError::InvalidRecoveryKey => 412,
+        }
+    }
+
+    /// Decodes the program outputs of a failed transaction
+    pub fn from_program_outputs(program_outputs: &[u8]) -> Option<Self> {
+        let bytes = hex::decode(program_outputs).ok()?;
+        borsh::from_slice(&bytes).ok()
+    }
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::Sdk(e) => write!(f, "{e}"),
+            WalletError::InvalidIdentity { expected, got } => {
+                write!(f, "Invalid identity: expected {expected}, got {got}")
+            }
+            WalletError::MissingTxContext => write!(f, "tx_ctx is missing"),
+            WalletError::IncompleteCalldata => write!(
+                f,
+                "All blobs should be in the Calldata for whitelist validation"
+            ),
+            WalletError::MissingBlob(contract) => write!(f, "Missing {contract} blob"),
+            WalletError::InvalidBlob(contract) => write!(f, "Invalid {contract} blob"),
+            WalletError::InvalidAction => write!(f, "Invalid action"),
+            WalletError::MissingCommitmentMetadata => {
+                write!(f, "Missing commitment metadata for the transaction")
+            }
+            WalletError::InvalidCommitmentMetadata => {
+                write!(f, "Commitment metadata does not match the state root")
+            }
+            WalletError::AccountNotProven(account) => {
+                write!(f, "Account {account} missing from commitment metadata")
+            }
+            WalletError::IdentityNotFound => write!(f, "Identity not found"),
+            WalletError::IdentityAlreadyExists => write!(f, "Identity already exists"),
+            WalletError::InvalidNonce => write!(f, "Invalid nonce"),
+            WalletError::NoAuthMethod => write!(f, "No authentication method"),
+            WalletError::InvalidAuthentication => write!(f, "Invalid authentication"),
+            WalletError::AuthMethodAlreadyExists => {
+                write!(f, "Authentication method already exists")
+            }
+            WalletError::AuthMethodNotFound => write!(f, "Authentication method not found"),
+            WalletError::LastAuthMethod => {
+                write!(f, "Cannot remove the last authentication method")
+            }
+            WalletError::InvalidPublicKey => write!(f, "Invalid public key"),
+            WalletError::InvalidSignedMessage => write!(f, "Invalid signed message"),
+            WalletError::SignerIdentityMismatch => {
+                write!(f, "Signature blob identity does not match")
+            }
+            WalletError::InvalidAuthenticatorData => write!(f, "Invalid authenticator data"),
+            WalletError::UserPresenceNotAsserted => write!(f, "User presence not asserted"),
+            WalletError::InvalidClientData => write!(f, "Invalid client data"),
+            WalletError::InvalidClientDataType => write!(f, "Invalid client data type"),
+            WalletError::InvalidChallenge => write!(f, "Invalid WebAuthn challenge"),
+            WalletError::InvalidRelyingParty => write!(f, "Invalid WebAuthn relying party"),
+            WalletError::InvalidOrigin => write!(f, "Invalid WebAuthn origin"),
+            WalletError::InvalidWebAuthnSignature => write!(f, "Invalid WebAuthn signature"),
+            WalletError::SessionKeyAlreadyExists => write!(f, "Session key already exists"),
+            WalletError::SessionKeyNotFound => write!(f, "Session key not found"),
+            WalletError::SessionKeyExpired => write!(f, "Session key expired"),
+            WalletError::SessionKeyNonceAlreadyUsed => write!(f, "Session key nonce already used"),
+            WalletError::NotWhitelisted(contract) => {
+                write!(f, "Blob: {} not whitelisted", contract.0)
+            }
+            WalletError::WrongLane => write!(f, "Session key not valid for this lane"),
+            WalletError::NotATokenAction(contract) => {
+                write!(f, "Blob: {} is not a token action", contract.0)
+            }
+            WalletError::ActionNotAllowed {
+                contract_name,
+                action,
+            } => write!(f, "Action {action:?} not allowed on {}", contract_name.0),
+            WalletError::RecipientNotAllowed {
+                contract_name,
+                recipient,
+            } => write!(
+                f,
+                "Recipient {} not allowed on {}",
+                recipient.0, contract_name.0
+            ),
+            WalletError::TransactionLimitExceeded(contract) => {
+                write!(
+                    f,
+                    "Spending limit per transaction exceeded for {}",
+                    contract.0
+                )
+            }
+            WalletError::WindowLimitExceeded(contract) => {
+                write!(f, "Spending limit per window exceeded for {}", contract.0)
+            }
+            WalletError::GuardianNotFound => write!(f, "Guardian identity not found"),
+            WalletError::SelfGuardian => write!(f, "An account cannot be its own guardian"),
+            WalletError::DuplicateGuardian => write!(f, "Duplicate guardian"),
+            WalletError::InvalidGuardianThreshold => write!(f, "Invalid guardian threshold"),
+            WalletError::NoGuardians => write!(f, "Account has no guardians"),
+            WalletError::NotAGuardian => write!(f, "Not a guardian of this account"),
+            WalletError::RecoveryPending => write!(f, "Another recovery is pending"),
+            WalletError::RecoveryAlreadyApproved => {
+                write!(f, "Recovery already approved by this guardian")
+            }
+            WalletError::RecoveryThresholdNotReached => {
+                write!(f, "Recovery threshold not reached")
+            }
+            WalletError::RecoveryDelayNotExpired => write!(f, "Recovery delay has not expired"),
+            WalletError::NoPendingRecovery => write!(f, "No pending recovery"),
+            WalletError::NoRecoveryKey => write!(f, "Account has no recovery key"),
+            WalletError::InvalidRecoveryKey => write!(f, "Invalid recovery key"),
+        }
+    }
+}
+
+/// Program outputs of a failed transaction
+impl From<WalletError> for String {
+    fn from(error: WalletError) -> Self {
+        hex::encode(borsh::to_vec(&error).expect("Failed to encode WalletError"))
+    }
+}
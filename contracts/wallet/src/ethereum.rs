@@ -0,0 +1,66 @@
+use k256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey};
+use sdk::verifiers::Secp256k1Blob;
+use sha3::{Digest, Keccak256};
+
+use crate::WalletError;
+
+/// The message an Ethereum account signs (with `personal_sign`) to perform `action`
+/// (the borsh-encoded wallet action) on `account`. The action is signed by its keccak hash,
+/// so a signature can't be reused for another action with the same name.
+pub fn message(account: &str, action: &[u8], nonce: u128) -> String {
+    let action = hex::encode(Keccak256::digest(action));
+    format!("Hyli wallet\naccount: {account}\naction: 0x{action}\nnonce: {nonce}")
+}
+
+/// EIP-191 (version 0x45) hash of `message`, as computed by Ethereum wallets
+pub fn eip191_hash(message: &str) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes());
+    hasher.update(message.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Ethereum address (last 20 bytes of the keccak of the uncompressed key) of a SEC1 public key
+pub fn address(public_key: &[u8]) -> Result<[u8; 20], WalletError> {
+    let public_key =
+        PublicKey::from_sec1_bytes(public_key).map_err(|_| WalletError::InvalidPublicKey)?;
+    let point = public_key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    let mut address = [0; 20];
+    address.copy_from_slice(&hash[12..]);
+    Ok(address)
+}
+
+/// Checks that the transaction carries a secp256k1 signature of the EIP-191 message for
+/// `account`, the wallet action of the transaction and `nonce`, made by the key controlling
+/// `expected_address`. The signature itself is checked by the native `secp256k1` verifier,
+/// over the blob's `data`.
+pub fn verify(
+    calldata: &sdk::Calldata,
+    expected_address: &str,
+    account: &str,
+    nonce: u128,
+) -> Result<(), WalletError> {
+    let blob = calldata
+        .blobs
+        .iter()
+        .find(|(_, b)| b.contract_name.0 == "secp256k1")
+        .map(|(_, b)| b)
+        .ok_or(WalletError::MissingBlob("secp256k1".to_string()))?;
+    let blob = borsh::from_slice::<Secp256k1Blob>(&blob.data.0)
+        .map_err(|_| WalletError::InvalidBlob("secp256k1".to_string()))?;
+
+    if blob.identity != calldata.identity {
+        return Err(WalletError::SignerIdentityMismatch);
+    }
+    let action = &crate::wallet_blob(calldata)?.data.0;
+    if blob.data != eip191_hash(&message(account, action, nonce)) {
+        return Err(WalletError::InvalidSignedMessage);
+    }
+
+    let signer = hex::encode(address(&blob.public_key)?);
+    if signer != expected_address.trim_start_matches("0x").to_lowercase() {
+        return Err(WalletError::InvalidAuthentication);
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 
 use borsh::{io::Error, BorshDeserialize, BorshSerialize};
 #[cfg(feature = "client")]
@@ -10,10 +10,49 @@ use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "client")]
 pub mod client;
+pub mod error;
+pub mod ethereum;
+pub mod limits;
+pub mod migration;
+pub mod output;
+pub mod recovery;
+pub mod smt;
+pub mod webauthn;
+
+pub use error::WalletError;
+pub use limits::{Permission, Spending, SpendingLimit, TokenActionKind, WindowLimit};
+pub use output::WalletOutput;
+pub use recovery::{Guardians, PendingKeyRecovery, PendingRecovery, RecoveryKey, RecoveryProposal};
+
+use smt::PartialState;
 
 impl sdk::ZkContract for Wallet {
     fn execute(&mut self, calldata: &sdk::Calldata) -> RunResult {
-        let (action, ctx) = sdk::utils::parse_raw_calldata::<WalletAction>(calldata)?;
+        let res = self
+            .load_next_partial_state()
+            .map_err(String::from)
+            .and_then(|()| self.execute_action(calldata));
+        if res.is_err() {
+            // A failed transaction leaves the accounts untouched
+            self.revert_partial_state();
+        }
+        res
+    }
+
+    /// Only the root of the accounts' merkle tree is committed on-chain.
+    fn commit(&self) -> sdk::StateCommitment {
+        sdk::StateCommitment(self.root().to_vec())
+    }
+}
+
+impl Wallet {
+    /// Errors are returned as the program outputs of the failed transaction, see [`WalletError`]
+    fn execute_action(&mut self, calldata: &sdk::Calldata) -> RunResult {
+        let (action, ctx) =
+            sdk::utils::parse_raw_calldata::<WalletAction>(calldata).map_err(WalletError::Sdk)?;
+
+        Self::check_identity(action.account(), calldata)?;
+        self.check_touched_accounts(&action)?;
 
         let res = match action {
             WalletAction::RegisterIdentity {
@@ -24,22 +63,50 @@ impl sdk::ZkContract for Wallet {
             WalletAction::UseSessionKey { account, nonce } => {
                 self.handle_session_key_usage(account, nonce, calldata)?
             }
+            WalletAction::RotatePassword { account, new_hash } => {
+                self.handle_password_rotation(account, new_hash, calldata)?
+            }
+            WalletAction::ApproveRecovery {
+                account,
+                guardian,
+                new_auth_method,
+            } => self.handle_guardian_action(account, guardian, Some(new_auth_method), calldata)?,
+            WalletAction::FinalizeRecovery { account, guardian } => {
+                self.handle_guardian_action(account, guardian, None, calldata)?
+            }
+            WalletAction::InitiateKeyRecovery {
+                account,
+                nonce,
+                new_auth_method,
+            } => {
+                self.handle_recovery_key_action(account, nonce, Some(new_auth_method), calldata)?
+            }
+            WalletAction::FinalizeKeyRecovery { account, nonce } => {
+                self.handle_recovery_key_action(account, nonce, None, calldata)?
+            }
+            // Checked by the action it authenticates
+            WalletAction::WebAuthnAssertion { .. } => WalletOutput::WebAuthnAssertion,
             _ => self.handle_authenticated_action(action, calldata)?,
         };
 
-        Ok((res.into_bytes(), ctx, vec![]))
-    }
-
-    /// In this example, we serialize the full state on-chain.
-    fn commit(&self) -> sdk::StateCommitment {
-        sdk::StateCommitment(borsh::to_vec(&self).expect("Failed to encode state"))
+        Ok((res.as_bytes(), ctx, vec![]))
     }
 }
 
-/// The state of the contract, that is totally serialized on-chain
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
+/// The state of the contract, as executed by the prover.
+/// Only the root of the sparse merkle tree of the accounts is committed on-chain: each
+/// transaction comes with the accounts it touches, proven against that root.
+/// The full tree is kept off-chain by `WalletProvableState`, behind the client feature.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub struct Wallet {
+    /// Root of the accounts' tree before the transaction being executed
+    root: [u8; 32],
+    /// Accounts touched by the transaction being executed
     identities: BTreeMap<String, AccountInfo>,
+    /// Proven accounts of the transaction being executed
+    current: Option<PartialState>,
+    /// Proven accounts of the next transactions, in execution order
+    batch: VecDeque<PartialState>,
 }
 
 /// Struct to hold account's information
@@ -49,9 +116,14 @@ pub struct Wallet {
     derive(client_sdk::contract_indexer::utoipa::ToSchema)
 )]
 pub struct AccountInfo {
-    pub auth_method: AuthMethod,
+    /// Authentication methods of the account, by label. Any of them can authenticate an action.
+    pub auth_methods: BTreeMap<String, AuthMethod>,
     pub session_keys: Vec<SessionKey>,
     pub nonce: u128,
+    pub guardians: Option<Guardians>,
+    pub pending_recovery: Option<PendingRecovery>,
+    pub recovery_key: Option<RecoveryKey>,
+    pub pending_key_recovery: Option<PendingKeyRecovery>,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -65,21 +137,50 @@ pub struct SessionKey {
     pub nonce: u128,
     pub whitelist: Option<Vec<ContractName>>,
     pub lane_id: Option<LaneId>,
+    /// Actions allowed on token contracts. Contracts absent from the list are only checked against the whitelist.
+    pub permissions: Vec<Permission>,
+    /// Caps on the tokens the key can move. No limit applies to tokens absent from the list.
+    pub spending_limits: Vec<SpendingLimit>,
+    /// Recent spendings, to enforce the rolling window limits
+    pub spendings: Vec<Spending>,
 }
 
+/// Label of the authentication method given at registration
+pub const DEFAULT_AUTH_METHOD: &str = "default";
+
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(
     feature = "client",
     derive(client_sdk::contract_indexer::utoipa::ToSchema)
 )]
 pub enum AuthMethod {
-    Password { hash: String },
+    Password {
+        hash: String,
+    },
+    /// A passkey, identified by its credential id and P-256 public key (hex encoded, SEC1 compressed).
+    /// Its assertions must be made for the relying party `rp_id`, from a page of `origin`.
+    WebAuthn {
+        credential_id: String,
+        public_key: String,
+        rp_id: String,
+        origin: String,
+    },
+    /// An Ethereum account (hex encoded 20-byte address), authenticated by an EIP-191 signature
+    EthereumAddress {
+        address: String,
+    },
     // Other authentication methods can be added here
 }
 
 impl AuthMethod {
-    // Verifies the authentication method during use
-    fn verify(&self, calldata: &sdk::Calldata) -> Result<String, String> {
+    // Verifies the authentication method during use.
+    // `nonce` is the account nonce the credential must be bound to.
+    fn verify(
+        &self,
+        account: &str,
+        nonce: u128,
+        calldata: &sdk::Calldata,
+    ) -> Result<(), WalletError> {
         match self {
             AuthMethod::Password { hash } => {
                 let check_secret = calldata
@@ -87,28 +188,76 @@ impl AuthMethod {
                     .iter()
                     .find(|(_, b)| b.contract_name.0 == "check_secret")
                     .map(|(_, b)| b.data.clone())
-                    .ok_or("Missing check_secret blob")?;
+                    .ok_or(WalletError::MissingBlob("check_secret".to_string()))?;
 
                 let checked_hash = hex::encode(check_secret.0);
                 if checked_hash != *hash {
-                    return Err("Invalid authentication".to_string());
+                    return Err(WalletError::InvalidAuthentication);
                 }
-                Ok("Authentication successful".to_string())
+                Ok(())
+            }
+            AuthMethod::WebAuthn {
+                public_key,
+                rp_id,
+                origin,
+                ..
+            } => webauthn::verify(calldata, public_key, rp_id, origin, account, nonce),
+            AuthMethod::EthereumAddress { address } => {
+                ethereum::verify(calldata, address, account, nonce)
             }
         }
     }
 }
 
+impl AccountInfo {
+    /// Succeeds if any of the account's authentication methods is satisfied by the transaction
+    fn authenticate(&self, account: &str, calldata: &sdk::Calldata) -> Result<(), WalletError> {
+        let mut error = WalletError::NoAuthMethod;
+        for auth_method in self.auth_methods.values() {
+            match auth_method.verify(account, self.nonce, calldata) {
+                Ok(()) => return Ok(()),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+}
+
+/// The blob of the transaction being executed by the wallet contract
+fn wallet_blob(calldata: &sdk::Calldata) -> Result<&sdk::Blob, WalletError> {
+    calldata
+        .blobs
+        .iter()
+        .find(|(index, _)| index == &calldata.index)
+        .map(|(_, blob)| blob)
+        .ok_or(WalletError::MissingBlob("wallet".to_string()))
+}
+
 /// Methods to handle the actions of the Wallet contract
 impl Wallet {
+    /// Ensures the transaction is sent by `{account}@{contract_name}`, so that an identity
+    /// cannot act on behalf of another account.
+    fn check_identity(account: &str, calldata: &sdk::Calldata) -> Result<(), WalletError> {
+        let contract_name = &wallet_blob(calldata)?.contract_name;
+
+        let expected = format!("{account}@{}", contract_name.0);
+        if calldata.identity.0 != expected {
+            return Err(WalletError::InvalidIdentity {
+                expected,
+                got: calldata.identity.0.clone(),
+            });
+        }
+        Ok(())
+    }
+
     fn handle_registration(
         &mut self,
         account: String,
         nonce: u128,
         auth_method: AuthMethod,
         calldata: &sdk::Calldata,
-    ) -> Result<String, String> {
-        auth_method.verify(calldata)?;
+    ) -> Result<WalletOutput, WalletError> {
+        auth_method.verify(&account, nonce, calldata)?;
         self.register_identity(account, nonce, auth_method)
     }
 
@@ -117,30 +266,64 @@ impl Wallet {
         account: String,
         nonce: u128,
         calldata: &sdk::Calldata,
-    ) -> Result<String, String> {
-        let secp256k1blob = CheckSecp256k1::new(calldata, nonce.to_string().as_bytes()).expect()?;
+    ) -> Result<WalletOutput, WalletError> {
+        let secp256k1blob = CheckSecp256k1::new(calldata, nonce.to_string().as_bytes())
+            .expect()
+            .map_err(WalletError::Sdk)?;
         let public_key = hex::encode(secp256k1blob.public_key);
         self.use_session_key(account, public_key, calldata, nonce)
     }
 
+    fn handle_password_rotation(
+        &mut self,
+        account: String,
+        new_hash: String,
+        calldata: &sdk::Calldata,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        // Only the current password can rotate itself
+        let label = stored_info
+            .auth_methods
+            .iter()
+            .find(|(_, auth_method)| {
+                matches!(auth_method, AuthMethod::Password { .. })
+                    && auth_method
+                        .verify(&account, stored_info.nonce, calldata)
+                        .is_ok()
+            })
+            .map(|(label, _)| label.clone())
+            .ok_or(WalletError::InvalidAuthentication)?;
+
+        self.rotate_password(account, label, new_hash)
+    }
+
     fn handle_authenticated_action(
         &mut self,
         action: WalletAction,
         calldata: &sdk::Calldata,
-    ) -> Result<String, String> {
+    ) -> Result<WalletOutput, WalletError> {
         let account = match &action {
             WalletAction::VerifyIdentity { account, .. }
             | WalletAction::AddSessionKey { account, .. }
-            | WalletAction::RemoveSessionKey { account, .. } => account,
-            _ => return Err("Invalid action".to_string()),
+            | WalletAction::RemoveSessionKey { account, .. }
+            | WalletAction::AddAuthMethod { account, .. }
+            | WalletAction::RemoveAuthMethod { account, .. }
+            | WalletAction::SetGuardians { account, .. }
+            | WalletAction::SetRecoveryKey { account, .. }
+            | WalletAction::CancelRecovery { account } => account,
+            _ => return Err(WalletError::InvalidAction),
         };
 
         // Verify identity before executing the action
-        let stored_info = self.identities.get(account).ok_or(format!(
-            "Identity not found: {account}, identities: {:?}",
-            self.identities
-        ))?;
-        stored_info.auth_method.verify(calldata)?;
+        let stored_info = self
+            .identities
+            .get(account)
+            .ok_or(WalletError::IdentityNotFound)?;
+        stored_info.authenticate(account, calldata)?;
 
         match action {
             WalletAction::VerifyIdentity { account, nonce } => self.verify_identity(account, nonce),
@@ -150,10 +333,39 @@ impl Wallet {
                 expiration_date,
                 whitelist,
                 lane_id,
-            } => self.add_session_key(account, key, expiration_date, whitelist, lane_id),
+                permissions,
+                spending_limits,
+            } => self.add_session_key(
+                account,
+                key,
+                expiration_date,
+                whitelist,
+                lane_id,
+                permissions,
+                spending_limits,
+            ),
             WalletAction::RemoveSessionKey { account, key } => {
                 self.remove_session_key(account, key)
             }
+            WalletAction::AddAuthMethod {
+                account,
+                label,
+                auth_method,
+            } => self.add_auth_method(account, label, auth_method),
+            WalletAction::RemoveAuthMethod { account, label } => {
+                self.remove_auth_method(account, label)
+            }
+            WalletAction::SetGuardians {
+                account,
+                guardians,
+                threshold,
+                delay,
+            } => self.set_guardians(account, guardians, threshold, delay),
+            WalletAction::SetRecoveryKey {
+                account,
+                recovery_key,
+            } => self.set_recovery_key(account, recovery_key),
+            WalletAction::CancelRecovery { account } => self.cancel_recovery(account),
             _ => unreachable!(),
         }
     }
@@ -166,11 +378,15 @@ impl Wallet {
         account: String,
         nonce: u128,
         auth_method: AuthMethod,
-    ) -> Result<String, String> {
+    ) -> Result<WalletOutput, WalletError> {
         let account_info = AccountInfo {
-            auth_method,
+            auth_methods: BTreeMap::from([(DEFAULT_AUTH_METHOD.to_string(), auth_method)]),
             session_keys: Vec::new(),
             nonce,
+            guardians: None,
+            pending_recovery: None,
+            recovery_key: None,
+            pending_key_recovery: None,
         };
 
         if self
@@ -178,25 +394,87 @@ impl Wallet {
             .insert(account.clone(), account_info)
             .is_some()
         {
-            return Err("Identity already exists".to_string());
+            return Err(WalletError::IdentityAlreadyExists);
         }
-        Ok(format!(
-            "Successfully registered identity for account: {account}"
-        ))
+        Ok(WalletOutput::Registered)
     }
 
-    fn verify_identity(&mut self, account: String, nonce: u128) -> Result<String, String> {
+    fn add_auth_method(
+        &mut self,
+        account: String,
+        label: String,
+        auth_method: AuthMethod,
+    ) -> Result<WalletOutput, WalletError> {
         let stored_info = self
             .identities
             .get_mut(&account)
-            .ok_or("Identity not found")?;
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        if stored_info.auth_methods.contains_key(&label) {
+            return Err(WalletError::AuthMethodAlreadyExists);
+        }
+
+        stored_info.auth_methods.insert(label.clone(), auth_method);
+        stored_info.nonce += 1;
+        Ok(WalletOutput::AuthMethodAdded { label })
+    }
+
+    fn remove_auth_method(
+        &mut self,
+        account: String,
+        label: String,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        if !stored_info.auth_methods.contains_key(&label) {
+            return Err(WalletError::AuthMethodNotFound);
+        }
+        if stored_info.auth_methods.len() == 1 {
+            return Err(WalletError::LastAuthMethod);
+        }
+
+        stored_info.auth_methods.remove(&label);
+        stored_info.nonce += 1;
+        Ok(WalletOutput::AuthMethodRemoved { label })
+    }
+
+    fn rotate_password(
+        &mut self,
+        account: String,
+        label: String,
+        new_hash: String,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        stored_info
+            .auth_methods
+            .insert(label, AuthMethod::Password { hash: new_hash });
+        stored_info.nonce += 1;
+        Ok(WalletOutput::PasswordChanged)
+    }
+
+    fn verify_identity(
+        &mut self,
+        account: String,
+        nonce: u128,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
 
         if nonce <= stored_info.nonce {
-            return Err("Invalid nonce".to_string());
+            return Err(WalletError::InvalidNonce);
         }
 
         stored_info.nonce = nonce;
-        Ok("Identity verified".to_string())
+        Ok(WalletOutput::Verified { nonce })
     }
 
     fn add_session_key(
@@ -206,46 +484,58 @@ impl Wallet {
         expiration_date: u128,
         whitelist: Option<Vec<ContractName>>,
         lane_id: Option<LaneId>,
-    ) -> Result<String, String> {
+        permissions: Vec<Permission>,
+        spending_limits: Vec<SpendingLimit>,
+    ) -> Result<WalletOutput, WalletError> {
         let stored_info = self
             .identities
             .get_mut(&account)
-            .ok_or("Identity not found")?;
+            .ok_or(WalletError::IdentityNotFound)?;
 
         if stored_info
             .session_keys
             .iter()
             .any(|sk| sk.public_key == key)
         {
-            return Err("Session key already exists".to_string());
+            return Err(WalletError::SessionKeyAlreadyExists);
         }
 
         stored_info.session_keys.push(SessionKey {
-            public_key: key,
+            public_key: key.clone(),
             expiration_date: TimestampMs(expiration_date),
             nonce: 0, // Initialize nonce at 0
             whitelist,
             lane_id,
+            permissions,
+            spending_limits,
+            spendings: Vec::new(),
         });
         stored_info.nonce += 1;
-        Ok("Session key added".to_string())
+        Ok(WalletOutput::SessionKeyAdded {
+            key,
+            expiration: expiration_date,
+        })
     }
 
-    fn remove_session_key(&mut self, account: String, key: String) -> Result<String, String> {
+    fn remove_session_key(
+        &mut self,
+        account: String,
+        key: String,
+    ) -> Result<WalletOutput, WalletError> {
         let stored_info = self
             .identities
             .get_mut(&account)
-            .ok_or("Identity not found")?;
+            .ok_or(WalletError::IdentityNotFound)?;
 
         let initial_len = stored_info.session_keys.len();
         stored_info.session_keys.retain(|sk| sk.public_key != key);
 
         if stored_info.session_keys.len() == initial_len {
-            return Err("Session key not found".to_string());
+            return Err(WalletError::SessionKeyNotFound);
         }
 
         stored_info.nonce += 1;
-        Ok("Session key removed".to_string())
+        Ok(WalletOutput::SessionKeyRemoved)
     }
 
     fn use_session_key(
@@ -254,13 +544,13 @@ impl Wallet {
         public_key: String,
         calldata: &sdk::Calldata,
         nonce: u128,
-    ) -> Result<String, String> {
+    ) -> Result<WalletOutput, WalletError> {
         let Some(tx_ctx) = &calldata.tx_ctx else {
-            return Err("tx_ctx is missing".to_string());
+            return Err(WalletError::MissingTxContext);
         };
         // Check that all blobs of the transaction are in the Calldata
         if calldata.blobs.len() != calldata.tx_blob_count {
-            return Err("All blobs should be in the Calldata for whitelist validation".to_string());
+            return Err(WalletError::IncompleteCalldata);
         }
         match self.identities.get_mut(&account) {
             Some(stored_info) => {
@@ -279,9 +569,8 @@ impl Wallet {
                         }
                         if let Some(ref whitelist) = session_key.whitelist {
                             if !whitelist.contains(&blob.contract_name) {
-                                return Err(format!(
-                                    "Blob: {} not whitelisted",
-                                    blob.contract_name.0
+                                return Err(WalletError::NotWhitelisted(
+                                    blob.contract_name.clone(),
                                 ));
                             }
                         }
@@ -289,18 +578,27 @@ impl Wallet {
                     if session_key.lane_id.is_some()
                         && session_key.lane_id.as_ref() != Some(&tx_ctx.lane_id)
                     {
-                        return Err("Session key not valid for this lane".to_string());
+                        return Err(WalletError::WrongLane);
+                    }
+                    // Nonces must strictly increase, otherwise a captured signature could be replayed
+                    if nonce <= session_key.nonce {
+                        return Err(WalletError::SessionKeyNonceAlreadyUsed);
                     }
                     if session_key.expiration_date > tx_ctx.timestamp {
+                        session_key.check_permissions(calldata)?;
+                        session_key.spend(calldata, &tx_ctx.timestamp)?;
                         session_key.nonce = nonce;
-                        return Ok("Session key is valid".to_string());
+                        return Ok(WalletOutput::SessionKeyUsed {
+                            key: public_key,
+                            nonce,
+                        });
                     } else {
-                        return Err("Session key expired".to_string());
+                        return Err(WalletError::SessionKeyExpired);
                     }
                 }
-                Err("Session key not found".to_string())
+                Err(WalletError::SessionKeyNotFound)
             }
-            None => Err("Identity not found".to_string()),
+            None => Err(WalletError::IdentityNotFound),
         }
     }
 }
@@ -309,12 +607,18 @@ impl Wallet {
 impl Wallet {
     pub fn new() -> Self {
         Wallet {
+            root: [0; 32],
             identities: BTreeMap::new(),
+            current: None,
+            batch: VecDeque::new(),
         }
     }
 
-    pub fn get_nonce(&self, username: &str) -> Result<u128, &'static str> {
-        let info = self.identities.get(username).ok_or("Identity not found")?;
+    pub fn get_nonce(&self, username: &str) -> Result<u128, WalletError> {
+        let info = self
+            .identities
+            .get(username)
+            .ok_or(WalletError::IdentityNotFound)?;
         Ok(info.nonce)
     }
 
@@ -324,6 +628,9 @@ impl Wallet {
 }
 
 /// Enum representing the actions that can be performed by the IdentityVerification contract.
+///
+/// Actions, their [`WalletOutput`] and [`WalletError`], and the accounts and snapshots of [`migration`]
+/// are Borsh encoded, which encodes the variants of an enum by position: add new ones at the end.
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub enum WalletAction {
     RegisterIdentity {
@@ -341,6 +648,8 @@ pub enum WalletAction {
         expiration_date: u128,
         whitelist: Option<Vec<ContractName>>,
         lane_id: Option<LaneId>,
+        permissions: Vec<Permission>,
+        spending_limits: Vec<SpendingLimit>,
     },
     RemoveSessionKey {
         account: String,
@@ -350,9 +659,121 @@ pub enum WalletAction {
         account: String,
         nonce: u128,
     },
+    AddAuthMethod {
+        account: String,
+        label: String,
+        auth_method: AuthMethod,
+    },
+    RemoveAuthMethod {
+        account: String,
+        label: String,
+    },
+    /// Replaces the password proven by the transaction's `check_secret` blob
+    RotatePassword {
+        account: String,
+        new_hash: String,
+    },
+    /// Sets the guardians able to recover the account. An empty list removes them.
+    SetGuardians {
+        account: String,
+        guardians: Vec<String>,
+        threshold: u32,
+        delay: u128,
+    },
+    /// Sent by `guardian` to approve replacing the auth methods of `account` with `new_auth_method`
+    ApproveRecovery {
+        account: String,
+        guardian: String,
+        new_auth_method: AuthMethod,
+    },
+    /// Sent by `guardian` to execute an approved recovery once its delay expired
+    FinalizeRecovery {
+        account: String,
+        guardian: String,
+    },
+    /// Sets the key able to reset the auth methods after a delay. `None` removes it.
+    SetRecoveryKey {
+        account: String,
+        recovery_key: Option<RecoveryKey>,
+    },
+    /// Signed by the recovery key, starts the delay after which `new_auth_method` replaces the auth methods
+    InitiateKeyRecovery {
+        account: String,
+        nonce: u128,
+        new_auth_method: AuthMethod,
+    },
+    /// Signed by the recovery key, executes the recovery once its delay expired
+    FinalizeKeyRecovery {
+        account: String,
+        nonce: u128,
+    },
+    /// Sent by the owner to cancel the pending recoveries
+    CancelRecovery {
+        account: String,
+    },
+    /// Passkey assertion authenticating the other wallet action of the transaction
+    WebAuthnAssertion {
+        account: String,
+        assertion: webauthn::Secp256r1Blob,
+    },
 }
 
 impl WalletAction {
+    /// The account sending the action: the transaction identity must be `{account}@{contract_name}`
+    pub fn account(&self) -> &str {
+        match self {
+            WalletAction::ApproveRecovery { guardian, .. }
+            | WalletAction::FinalizeRecovery { guardian, .. } => guardian,
+            WalletAction::RegisterIdentity { account, .. }
+            | WalletAction::VerifyIdentity { account, .. }
+            | WalletAction::AddSessionKey { account, .. }
+            | WalletAction::RemoveSessionKey { account, .. }
+            | WalletAction::UseSessionKey { account, .. }
+            | WalletAction::AddAuthMethod { account, .. }
+            | WalletAction::RemoveAuthMethod { account, .. }
+            | WalletAction::RotatePassword { account, .. }
+            | WalletAction::SetGuardians { account, .. }
+            | WalletAction::SetRecoveryKey { account, .. }
+            | WalletAction::InitiateKeyRecovery { account, .. }
+            | WalletAction::FinalizeKeyRecovery { account, .. }
+            | WalletAction::CancelRecovery { account }
+            | WalletAction::WebAuthnAssertion { account, .. } => account,
+        }
+    }
+
+    /// Name of the action
+    pub fn name(&self) -> &'static str {
+        match self {
+            WalletAction::RegisterIdentity { .. } => "RegisterIdentity",
+            WalletAction::VerifyIdentity { .. } => "VerifyIdentity",
+            WalletAction::AddSessionKey { .. } => "AddSessionKey",
+            WalletAction::RemoveSessionKey { .. } => "RemoveSessionKey",
+            WalletAction::UseSessionKey { .. } => "UseSessionKey",
+            WalletAction::AddAuthMethod { .. } => "AddAuthMethod",
+            WalletAction::RemoveAuthMethod { .. } => "RemoveAuthMethod",
+            WalletAction::RotatePassword { .. } => "RotatePassword",
+            WalletAction::SetGuardians { .. } => "SetGuardians",
+            WalletAction::ApproveRecovery { .. } => "ApproveRecovery",
+            WalletAction::FinalizeRecovery { .. } => "FinalizeRecovery",
+            WalletAction::SetRecoveryKey { .. } => "SetRecoveryKey",
+            WalletAction::InitiateKeyRecovery { .. } => "InitiateKeyRecovery",
+            WalletAction::FinalizeKeyRecovery { .. } => "FinalizeKeyRecovery",
+            WalletAction::CancelRecovery { .. } => "CancelRecovery",
+            WalletAction::WebAuthnAssertion { .. } => "WebAuthnAssertion",
+        }
+    }
+
+    /// Accounts read or written by the action, which its commitment metadata must prove
+    pub fn touched_accounts(&self) -> Vec<&str> {
+        match self {
+            WalletAction::ApproveRecovery {
+                account, guardian, ..
+            }
+            | WalletAction::FinalizeRecovery { account, guardian } => vec![account, guardian],
+            _ => vec![self.account()],
+        }
+    }
+
     pub fn as_blob(&self, contract_name: sdk::ContractName) -> sdk::Blob {
         sdk::Blob {
             contract_name,
@@ -366,14 +787,3 @@ impl Default for Wallet {
         Self::new()
     }
 }
-
-/// Helpers to transform the contrat's state in its on-chain state digest version.
-/// In an optimal version, you would here only returns a hash of the state,
-/// while storing the full-state off-chain
-impl From<sdk::StateCommitment> for Wallet {
-    fn from(state: sdk::StateCommitment) -> Self {
-        borsh::from_slice(&state.0)
-            .map_err(|_| "Could not decode identity state".to_string())
-            .unwrap()
-    }
-}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hyle_smt_token::SmtTokenAction;
+use sdk::{hyle_model_utils::TimestampMs, ContractName, Identity};
+use serde::{Deserialize, Serialize};
+
+use crate::{SessionKey, WalletError};
+
+/// Token actions a session key can be allowed to perform
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub enum TokenActionKind {
+    Transfer,
+    TransferFrom,
+    Approve,
+}
+
+/// Restricts what a session key can do on a token contract
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct Permission {
+    pub contract_name: ContractName,
+    pub actions: Vec<TokenActionKind>,
+    /// When set, tokens can only be sent to (or approved for) these identities
+    pub recipients: Option<Vec<Identity>>,
+}
+
+/// Caps on the amount of a token a session key can move
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct SpendingLimit {
+    pub contract_name: ContractName,
+    /// Maximum amount moved by a single transaction
+    pub per_transaction: Option<u128>,
+    /// Maximum amount moved over any rolling window
+    pub per_window: Option<WindowLimit>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct WindowLimit {
+    /// Duration of the window, in ms
+    pub duration: u128,
+    pub amount: u128,
+}
+
+/// An amount of a token moved by a session key
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct Spending {
+    pub contract_name: ContractName,
+    pub timestamp: TimestampMs,
+    pub amount: u128,
+}
+
+impl Spending {
+    fn is_within(&self, window: &WindowLimit, now: &TimestampMs) -> bool {
+        self.timestamp.0.saturating_add(window.duration) > now.0
+    }
+}
+
+/// Kind of a token action, and the identity receiving the tokens (or the allowance)
+fn kind_and_recipient(action: &SmtTokenAction) -> (TokenActionKind, &Identity) {
+    match action {
+        SmtTokenAction::Transfer { recipient, .. } => (TokenActionKind::Transfer, recipient),
+        SmtTokenAction::TransferFrom { recipient, .. } => {
+            (TokenActionKind::TransferFrom, recipient)
+        }
+        SmtTokenAction::Approve { spender, .. } => (TokenActionKind::Approve, spender),
+    }
+}
+
+/// Amount of the `identity`'s tokens a token action moves or allows to move
+fn spent_by(action: &SmtTokenAction, identity: &Identity) -> u128 {
+    match action {
+        SmtTokenAction::Transfer { sender, amount, .. } if sender == identity => *amount,
+        SmtTokenAction::TransferFrom { owner, amount, .. } if owner == identity => *amount,
+        SmtTokenAction::Approve { owner, amount, .. } if owner == identity => *amount,
+        _ => 0,
+    }
+}
+
+impl SessionKey {
+    /// Checks the blobs of contracts the key has permissions on only perform allowed actions
+    pub(crate) fn check_permissions(&self, calldata: &sdk::Calldata) -> Result<(), WalletError> {
+        for (index, blob) in &calldata.blobs {
+            if index == &calldata.index {
+                continue;
+            }
+            let Some(permission) = self
+                .permissions
+                .iter()
+                .find(|permission| permission.contract_name == blob.contract_name)
+            else {
+                continue;
+            };
+            let action = borsh::from_slice::<SmtTokenAction>(&blob.data.0)
+                .map_err(|_| WalletError::NotATokenAction(blob.contract_name.clone()))?;
+
+            let (kind, recipient) = kind_and_recipient(&action);
+            if !permission.actions.contains(&kind) {
+                return Err(WalletError::ActionNotAllowed {
+                    contract_name: blob.contract_name.clone(),
+                    action: kind,
+                });
+            }
+            if let Some(recipients) = &permission.recipients {
+                if !recipients.contains(recipient) {
+                    return Err(WalletError::RecipientNotAllowed {
+                        contract_name: blob.contract_name.clone(),
+                        recipient: recipient.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the token blobs of the transaction against the spending limits of the key,
+    /// and records the amounts spent.
+    pub(crate) fn spend(
+        &mut self,
+        calldata: &sdk::Calldata,
+        now: &TimestampMs,
+    ) -> Result<(), WalletError> {
+        if self.spending_limits.is_empty() {
+            return Ok(());
+        }
+
+        let mut spent: BTreeMap<&ContractName, u128> = BTreeMap::new();
+        for (index, blob) in &calldata.blobs {
+            if index == &calldata.index {
+                continue;
+            }
+            if !self
+                .spending_limits
+                .iter()
+                .any(|limit| limit.contract_name == blob.contract_name)
+            {
+                continue;
+            }
+            let action = borsh::from_slice::<SmtTokenAction>(&blob.data.0)
+                .map_err(|_| WalletError::NotATokenAction(blob.contract_name.clone()))?;
+            let total = spent.entry(&blob.contract_name).or_default();
+            *total = total.saturating_add(spent_by(&action, &calldata.identity));
+        }
+
+        for limit in self.spending_limits.iter() {
+            let amount = spent.get(&limit.contract_name).copied().unwrap_or(0);
+            if amount == 0 {
+                continue;
+            }
+            if limit.per_transaction.is_some_and(|max| amount > max) {
+                return Err(WalletError::TransactionLimitExceeded(
+                    limit.contract_name.clone(),
+                ));
+            }
+            if let Some(window) = &limit.per_window {
+                let already_spent: u128 = self
+                    .spendings
+                    .iter()
+                    .filter(|s| s.contract_name == limit.contract_name && s.is_within(window, now))
+                    .map(|s| s.amount)
+                    .fold(0, u128::saturating_add);
+                if already_spent.saturating_add(amount) > window.amount {
+                    return Err(WalletError::WindowLimitExceeded(
+                        limit.contract_name.clone(),
+                    ));
+                }
+            }
+        }
+
+        // Only keep the spendings that are still within their window
+        let spending_limits = &self.spending_limits;
+        self.spendings.retain(|s| {
+            spending_limits.iter().any(|limit| {
+                limit.contract_name == s.contract_name
+                    && limit
+                        .per_window
+                        .as_ref()
+                        .is_some_and(|window| s.is_within(window, now))
+            })
+        });
+        for (contract_name, amount) in spent {
+            if amount > 0 {
+                self.spendings.push(Spending {
+                    contract_name: contract_name.clone(),
+                    timestamp: now.clone(),
+                    amount,
+                });
+            }
+        }
+        Ok(())
+    }
+}
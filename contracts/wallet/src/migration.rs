@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use borsh::{io::Read, BorshDeserialize, BorshSerialize};
+
+use crate::{AccountInfo, AuthMethod, SessionKey, DEFAULT_AUTH_METHOD};
+
+/// Prefix of the versioned state snapshots. Snapshots without it are the untagged
+/// state of the first wallet, see [`v0`].
+pub const STATE_MAGIC: [u8; 4] = *b"wlts";
+
+/// Migrations run by this program, between consecutive account versions.
+/// An account is migrated when a transaction touches it, the state root is kept as is.
+pub const MIGRATIONS: &[&str] = &[
+    "V0 -> V1: the auth method becomes the `default` one, session keys get no permissions nor spending limits",
+];
+
+/// Layout of the first wallet, whose whole state was committed on-chain
+pub mod v0 {
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use sdk::{hyle_model_utils::TimestampMs, ContractName, LaneId};
+
+    #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Eq, PartialEq)]
+    pub struct AccountInfo {
+        pub auth_method: AuthMethod,
+        pub session_keys: Vec<SessionKey>,
+        pub nonce: u128,
+    }
+
+    #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Eq, PartialEq)]
+    pub struct SessionKey {
+        pub public_key: String,
+        pub expiration_date: TimestampMs,
+        pub nonce: u128,
+        pub whitelist: Option<Vec<ContractName>>,
+        pub lane_id: Option<LaneId>,
+    }
+
+    #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Eq, PartialEq)]
+    pub enum AuthMethod {
+        Password { hash: String },
+    }
+}
+
+/// An account, encoded in the version of the program that last wrote it.
+/// Leaves of the accounts' tree are hashes of this encoding: the contract migrates an account
+/// to the latest version when a transaction touches it.
+/// New versions come with a migration from the previous one.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Eq, PartialEq)]
+pub enum VersionedAccount {
+    V0(v0::AccountInfo),
+    V1(AccountInfo),
+}
+
+impl VersionedAccount {
+    pub fn is_latest(&self) -> bool {
+        matches!(self, VersionedAccount::V1(_))
+    }
+
+    /// Migrates the account through every version up to the latest one
+    pub fn migrate(self) -> AccountInfo {
+        match self {
+            VersionedAccount::V0(info) => info.into(),
+            VersionedAccount::V1(info) => info,
+        }
+    }
+}
+
+impl From<AccountInfo> for VersionedAccount {
+    fn from(info: AccountInfo) -> Self {
+        VersionedAccount::V1(info)
+    }
+}
+
+impl From<v0::AccountInfo> for AccountInfo {
+    fn from(info: v0::AccountInfo) -> Self {
+        AccountInfo {
+            auth_methods: BTreeMap::from([(
+                DEFAULT_AUTH_METHOD.to_string(),
+                info.auth_method.into(),
+            )]),
+            session_keys: info.session_keys.into_iter().map(Into::into).collect(),
+            nonce: info.nonce,
+            guardians: None,
+            pending_recovery: None,
+            recovery_key: None,
+            pending_key_recovery: None,
+        }
+    }
+}
+
+impl From<v0::SessionKey> for SessionKey {
+    fn from(key: v0::SessionKey) -> Self {
+        SessionKey {
+            public_key: key.public_key,
+            expiration_date: key.expiration_date,
+            nonce: key.nonce,
+            whitelist: key.whitelist,
+            lane_id: key.lane_id,
+            permissions: vec![],
+            spending_limits: vec![],
+            spendings: vec![],
+        }
+    }
+}
+
+impl From<v0::AuthMethod> for AuthMethod {
+    fn from(auth_method: v0::AuthMethod) -> Self {
+        match auth_method {
+            v0::AuthMethod::Password { hash } => AuthMethod::Password { hash },
+        }
+    }
+}
+
+/// Snapshot of all the accounts, as persisted off-chain and given to `construct_state`.
+/// It is encoded after [`STATE_MAGIC`].
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub enum VersionedState {
+    /// Accounts of the first wallet
+    V0(BTreeMap<String, v0::AccountInfo>),
+    /// Leaves of the accounts' tree
+    V1(BTreeMap<String, VersionedAccount>),
+}
+
+impl VersionedState {
+    /// Decodes a snapshot of any version, untagged bytes being the state of the first wallet
+    pub fn decode(mut bytes: &[u8]) -> borsh::io::Result<Self> {
+        let state = Self::decode_reader(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(borsh::io::Error::new(
+                borsh::io::ErrorKind::InvalidData,
+                "Unexpected bytes after the state",
+            ));
+        }
+        Ok(state)
+    }
+
+    /// Reads a snapshot of any version, leaving what follows it in `reader`.
+    /// The first wallet's state starts with its number of accounts, which never matches [`STATE_MAGIC`].
+    pub fn decode_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        let mut prefix = [0; STATE_MAGIC.len()];
+        reader.read_exact(&mut prefix)?;
+        if prefix == STATE_MAGIC {
+            Self::deserialize_reader(reader)
+        } else {
+            BTreeMap::deserialize_reader(&mut prefix.as_slice().chain(reader))
+                .map(VersionedState::V0)
+        }
+    }
+
+    pub fn encode(&self) -> borsh::io::Result<Vec<u8>> {
+        let mut bytes = STATE_MAGIC.to_vec();
+        BorshSerialize::serialize(self, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// The accounts, in the version they were written in
+    pub fn into_accounts(self) -> BTreeMap<String, VersionedAccount> {
+        match self {
+            VersionedState::V0(accounts) => accounts
+                .into_iter()
+                .map(|(account, info)| (account, VersionedAccount::V0(info)))
+                .collect(),
+            VersionedState::V1(accounts) => accounts,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+/// Outputs of the successful wallet actions, Borsh encoded in the program outputs.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+#[serde(tag = "type")]
+pub enum WalletOutput {
+    Registered,
+    Verified {
+        nonce: u128,
+    },
+    SessionKeyAdded {
+        key: String,
+        expiration: u128,
+    },
+    SessionKeyRemoved,
+    SessionKeyUsed {
+        key: String,
+        nonce: u128,
+    },
+    AuthMethodAdded {
+        label: String,
+    },
+    AuthMethodRemoved {
+        label: String,
+    },
+    PasswordChanged,
+    GuardiansUpdated,
+    RecoveryApproved {
+        /// Set once enough guardians approved
+        executable_at: Option<u128>,
+    },
+    RecoveryKeyUpdated,
+    RecoveryInitiated {
+        executable_at: u128,
+    },
+    RecoveryCancelled,
+    AccountRecovered,
+    WebAuthnAssertion,
+}
+
+impl WalletOutput {
+    /// Decodes the program outputs of a successful transaction
+    pub fn from_program_outputs(program_outputs: &[u8]) -> Option<Self> {
+        borsh::from_slice(program_outputs).ok()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        borsh::to_vec(self).expect("Failed to encode WalletOutput")
+    }
+}
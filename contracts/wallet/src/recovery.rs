@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sdk::{hyle_model_utils::TimestampMs, secp256k1::CheckSecp256k1};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    wallet_blob, AccountInfo, AuthMethod, Wallet, WalletError, WalletOutput, DEFAULT_AUTH_METHOD,
+};
+
+/// Accounts of this wallet allowed to recover an account, and how many of them must agree
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct Guardians {
+    pub accounts: Vec<String>,
+    pub threshold: u32,
+    /// Time (ms) between the threshold being reached and the recovery being executable
+    pub delay: u128,
+}
+
+/// A recovery approved by some of the guardians.
+/// Each guardian approves a single auth method, and may approve another one until the threshold is
+/// reached: a guardian proposing another method than the others can't block the recovery.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct PendingRecovery {
+    pub proposals: Vec<RecoveryProposal>,
+    /// Set once the approvals of a proposal reach the threshold, that proposal being executed
+    pub executable_at: Option<TimestampMs>,
+}
+
+/// An auth method proposed by guardians to recover an account
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct RecoveryProposal {
+    pub new_auth_method: AuthMethod,
+    pub approvals: Vec<String>,
+}
+
+impl PendingRecovery {
+    /// The proposal whose approvals reached the threshold, there's at most one
+    pub fn approved(&self, threshold: u32) -> Option<&RecoveryProposal> {
+        self.proposals
+            .iter()
+            .find(|proposal| proposal.approvals.len() >= threshold as usize)
+    }
+}
+
+/// A secp256k1 key allowed to reset the account's auth methods, after a delay
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct RecoveryKey {
+    /// Hex encoded, SEC1 compressed public key
+    pub public_key: String,
+    /// Time (ms) between the initiation of a recovery and its execution
+    pub delay: u128,
+}
+
+/// A reset of the auth methods initiated by the recovery key
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct PendingKeyRecovery {
+    pub new_auth_method: AuthMethod,
+    pub executable_at: TimestampMs,
+}
+
+impl AccountInfo {
+    /// Replaces the auth methods of a recovered account.
+    /// Whoever lost control of the account may also have leaked its session keys, so they are dropped.
+    fn recover(&mut self, new_auth_method: AuthMethod) {
+        self.auth_methods = BTreeMap::from([(DEFAULT_AUTH_METHOD.to_string(), new_auth_method)]);
+        self.session_keys.clear();
+        self.pending_recovery = None;
+        self.pending_key_recovery = None;
+        self.nonce += 1;
+    }
+}
+
+/// Recovery key methods for the Wallet contract
+impl Wallet {
+    /// Checks the transaction is signed by the recovery key of `account`, for the account's current nonce.
+    /// The recovery key signs the borsh-encoded wallet action.
+    pub(crate) fn handle_recovery_key_action(
+        &mut self,
+        account: String,
+        nonce: u128,
+        new_auth_method: Option<AuthMethod>,
+        calldata: &sdk::Calldata,
+    ) -> Result<WalletOutput, WalletError> {
+        let Some(tx_ctx) = &calldata.tx_ctx else {
+            return Err(WalletError::MissingTxContext);
+        };
+        let stored_info = self
+            .identities
+            .get(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+        let recovery_key = stored_info
+            .recovery_key
+            .as_ref()
+            .ok_or(WalletError::NoRecoveryKey)?;
+
+        let secp256k1blob = CheckSecp256k1::new(calldata, &wallet_blob(calldata)?.data.0)
+            .expect()
+            .map_err(WalletError::Sdk)?;
+        if hex::encode(secp256k1blob.public_key) != recovery_key.public_key {
+            return Err(WalletError::InvalidRecoveryKey);
+        }
+        if nonce != stored_info.nonce {
+            return Err(WalletError::InvalidNonce);
+        }
+
+        match new_auth_method {
+            Some(new_auth_method) => {
+                self.initiate_key_recovery(account, new_auth_method, tx_ctx.timestamp.clone())
+            }
+            None => self.finalize_key_recovery(account, tx_ctx.timestamp.clone()),
+        }
+    }
+
+    pub(crate) fn set_recovery_key(
+        &mut self,
+        account: String,
+        recovery_key: Option<RecoveryKey>,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        stored_info.recovery_key = recovery_key;
+        stored_info.pending_key_recovery = None;
+        stored_info.nonce += 1;
+        Ok(WalletOutput::RecoveryKeyUpdated)
+    }
+
+    fn initiate_key_recovery(
+        &mut self,
+        account: String,
+        new_auth_method: AuthMethod,
+        now: TimestampMs,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+        let delay = stored_info
+            .recovery_key
+            .as_ref()
+            .ok_or(WalletError::NoRecoveryKey)?
+            .delay;
+
+        if stored_info.pending_key_recovery.is_some() {
+            return Err(WalletError::RecoveryPending);
+        }
+        let executable_at = now.0 + delay;
+        stored_info.pending_key_recovery = Some(PendingKeyRecovery {
+            new_auth_method,
+            executable_at: TimestampMs(executable_at),
+        });
+        stored_info.nonce += 1;
+        Ok(WalletOutput::RecoveryInitiated { executable_at })
+    }
+
+    fn finalize_key_recovery(
+        &mut self,
+        account: String,
+        now: TimestampMs,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        let pending = stored_info
+            .pending_key_recovery
+            .as_ref()
+            .ok_or(WalletError::NoPendingRecovery)?;
+        if pending.executable_at > now {
+            return Err(WalletError::RecoveryDelayNotExpired);
+        }
+
+        let new_auth_method = pending.new_auth_method.clone();
+        stored_info.recover(new_auth_method);
+        Ok(WalletOutput::AccountRecovered)
+    }
+}
+
+/// Guardian recovery methods for the Wallet contract
+impl Wallet {
+    /// Authenticates `guardian` for an action on the recovery of `account`
+    pub(crate) fn handle_guardian_action(
+        &mut self,
+        account: String,
+        guardian: String,
+        new_auth_method: Option<AuthMethod>,
+        calldata: &sdk::Calldata,
+    ) -> Result<WalletOutput, WalletError> {
+        let Some(tx_ctx) = &calldata.tx_ctx else {
+            return Err(WalletError::MissingTxContext);
+        };
+        let guardian_info = self
+            .identities
+            .get(&guardian)
+            .ok_or(WalletError::GuardianNotFound)?;
+        guardian_info.authenticate(&guardian, calldata)?;
+
+        match new_auth_method {
+            Some(new_auth_method) => {
+                self.approve_recovery(account, guardian, new_auth_method, tx_ctx.timestamp.clone())
+            }
+            None => self.finalize_recovery(account, guardian, tx_ctx.timestamp.clone()),
+        }
+    }
+
+    pub(crate) fn set_guardians(
+        &mut self,
+        account: String,
+        accounts: Vec<String>,
+        threshold: u32,
+        delay: u128,
+    ) -> Result<WalletOutput, WalletError> {
+        if accounts.contains(&account) {
+            return Err(WalletError::SelfGuardian);
+        }
+        let mut deduplicated = accounts.clone();
+        deduplicated.sort();
+        deduplicated.dedup();
+        if deduplicated.len() != accounts.len() {
+            return Err(WalletError::DuplicateGuardian);
+        }
+        if !accounts.is_empty() && (threshold == 0 || threshold as usize > accounts.len()) {
+            return Err(WalletError::InvalidGuardianThreshold);
+        }
+
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        // An empty list removes the guardians
+        stored_info.guardians = (!accounts.is_empty()).then_some(Guardians {
+            accounts,
+            threshold,
+            delay,
+        });
+        stored_info.pending_recovery = None;
+        stored_info.nonce += 1;
+        Ok(WalletOutput::GuardiansUpdated)
+    }
+
+    /// Cancels the pending recoveries, whether initiated by guardians or by the recovery key
+    pub(crate) fn cancel_recovery(&mut self, account: String) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+
+        let guardian_recovery = stored_info.pending_recovery.take();
+        let key_recovery = stored_info.pending_key_recovery.take();
+        if guardian_recovery.is_none() && key_recovery.is_none() {
+            return Err(WalletError::NoPendingRecovery);
+        }
+        stored_info.nonce += 1;
+        Ok(WalletOutput::RecoveryCancelled)
+    }
+
+    fn approve_recovery(
+        &mut self,
+        account: String,
+        guardian: String,
+        new_auth_method: AuthMethod,
+        now: TimestampMs,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+        let guardians = stored_info
+            .guardians
+            .as_ref()
+            .ok_or(WalletError::NoGuardians)?;
+        if !guardians.accounts.contains(&guardian) {
+            return Err(WalletError::NotAGuardian);
+        }
+
+        let pending = stored_info
+            .pending_recovery
+            .get_or_insert_with(|| PendingRecovery {
+                proposals: Vec::new(),
+                executable_at: None,
+            });
+        let approved = |proposal: &RecoveryProposal| {
+            proposal.new_auth_method == new_auth_method && proposal.approvals.contains(&guardian)
+        };
+        if pending.proposals.iter().any(approved) {
+            return Err(WalletError::RecoveryAlreadyApproved);
+        }
+        // Once a proposal reached the threshold, only the owner cancelling it stops the recovery
+        if pending.executable_at.is_some() {
+            return Err(WalletError::RecoveryPending);
+        }
+
+        // The guardian's approval of another method is replaced
+        for proposal in pending.proposals.iter_mut() {
+            proposal.approvals.retain(|approval| approval != &guardian);
+        }
+        pending
+            .proposals
+            .retain(|proposal| !proposal.approvals.is_empty());
+        match pending
+            .proposals
+            .iter_mut()
+            .find(|proposal| proposal.new_auth_method == new_auth_method)
+        {
+            Some(proposal) => proposal.approvals.push(guardian),
+            None => pending.proposals.push(RecoveryProposal {
+                new_auth_method,
+                approvals: vec![guardian],
+            }),
+        }
+        if pending.approved(guardians.threshold).is_some() {
+            pending.executable_at = Some(TimestampMs(now.0 + guardians.delay));
+        }
+        Ok(WalletOutput::RecoveryApproved {
+            executable_at: pending.executable_at.as_ref().map(|t| t.0),
+        })
+    }
+
+    fn finalize_recovery(
+        &mut self,
+        account: String,
+        guardian: String,
+        now: TimestampMs,
+    ) -> Result<WalletOutput, WalletError> {
+        let stored_info = self
+            .identities
+            .get_mut(&account)
+            .ok_or(WalletError::IdentityNotFound)?;
+        let guardians = stored_info
+            .guardians
+            .as_ref()
+            .filter(|guardians| guardians.accounts.contains(&guardian))
+            .ok_or(WalletError::NotAGuardian)?;
+
+        let pending = stored_info
+            .pending_recovery
+            .as_ref()
+            .ok_or(WalletError::NoPendingRecovery)?;
+        match &pending.executable_at {
+            Some(executable_at) if *executable_at <= now => {}
+            Some(_) => return Err(WalletError::RecoveryDelayNotExpired),
+            None => return Err(WalletError::RecoveryThresholdNotReached),
+        }
+
+        let new_auth_method = pending
+            .approved(guardians.threshold)
+            .ok_or(WalletError::RecoveryThresholdNotReached)?
+            .new_auth_method
+            .clone();
+        stored_info.recover(new_auth_method);
+        Ok(WalletOutput::AccountRecovered)
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use sparse_merkle_tree::{traits::Hasher, CompiledMerkleProof, H256};
+
+use crate::{migration::VersionedAccount, AccountInfo, Wallet, WalletAction, WalletError};
+
+/// Hasher of the accounts' sparse merkle tree
+#[derive(Default)]
+pub struct SHA256Hasher(Sha256);
+
+impl Hasher for SHA256Hasher {
+    fn write_h256(&mut self, h: &H256) {
+        self.0.update(h.as_slice());
+    }
+
+    fn write_byte(&mut self, b: u8) {
+        self.0.update([b]);
+    }
+
+    fn finish(self) -> H256 {
+        let hash: [u8; 32] = self.0.finalize().into();
+        hash.into()
+    }
+}
+
+/// Key of an account's leaf
+pub fn account_key(account: &str) -> H256 {
+    let hash: [u8; 32] = Sha256::digest(account.as_bytes()).into();
+    hash.into()
+}
+
+/// Value of an account's leaf: the hash of its versioned encoding, zero if the account does not exist
+pub fn account_value(account: Option<&VersionedAccount>) -> H256 {
+    match account {
+        Some(account) => {
+            let hash: [u8; 32] =
+                Sha256::digest(borsh::to_vec(account).expect("Failed to encode account")).into();
+            hash.into()
+        }
+        None => H256::zero(),
+    }
+}
+
+/// Value of the leaf of an account written by this version of the contract
+pub fn latest_account_value(info: Option<&AccountInfo>) -> H256 {
+    account_value(info.cloned().map(VersionedAccount::from).as_ref())
+}
+
+/// The accounts a transaction touches, with a proof of their value against the state root.
+/// `None` proves that the account does not exist. Accounts are in the version they were written in.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+pub struct PartialState {
+    pub accounts: BTreeMap<String, Option<VersionedAccount>>,
+    /// Compiled merkle proof of `accounts`
+    pub proof: Vec<u8>,
+}
+
+impl PartialState {
+    /// Leaves of the accounts, valued by `value`
+    fn leaves(&self, value: impl Fn(&str) -> H256) -> Vec<(H256, H256)> {
+        let mut leaves: Vec<(H256, H256)> = self
+            .accounts
+            .keys()
+            .map(|account| (account_key(account), value(account)))
+            .collect();
+        leaves.sort_by_key(|(key, _)| *key);
+        leaves
+    }
+
+    /// The proven accounts that exist, migrated to the latest version
+    fn existing_accounts(&self) -> BTreeMap<String, AccountInfo> {
+        self.accounts
+            .iter()
+            .filter_map(|(account, info)| Some((account.clone(), info.clone()?.migrate())))
+            .collect()
+    }
+
+    /// Checks the accounts against `root`
+    pub fn verify(&self, root: [u8; 32]) -> bool {
+        let leaves = self
+            .leaves(|account| account_value(self.accounts.get(account).and_then(Option::as_ref)));
+        CompiledMerkleProof(self.proof.clone())
+            .verify::<SHA256Hasher>(&root.into(), leaves)
+            .unwrap_or(false)
+    }
+
+    /// Root of the tree once the accounts took the values of `identities`, in the latest version
+    pub fn compute_root(&self, identities: &BTreeMap<String, AccountInfo>) -> [u8; 32] {
+        let leaves = self.leaves(|account| latest_account_value(identities.get(account)));
+        CompiledMerkleProof(self.proof.clone())
+            .compute_root::<SHA256Hasher>(leaves)
+            .expect("Failed to compute state root")
+            .into()
+    }
+}
+
+impl Wallet {
+    /// Wallet executing the transactions whose accounts are proven by `batch`, against `root`
+    pub fn with_partial_states(root: [u8; 32], batch: Vec<PartialState>) -> Self {
+        Wallet {
+            root,
+            batch: batch.into(),
+            ..Wallet::new()
+        }
+    }
+
+    /// Appends the partial states of `other` to the ones to execute
+    pub fn merge(&mut self, other: Wallet) {
+        self.batch.extend(other.batch);
+    }
+
+    /// Root of the accounts' tree, with the changes of the transaction being executed
+    pub fn root(&self) -> [u8; 32] {
+        match &self.current {
+            Some(partial) => partial.compute_root(&self.identities),
+            None => self.root,
+        }
+    }
+
+    /// Accounts touched by the transaction being executed, `None` for the ones that do not exist
+    pub fn touched_accounts(&self) -> BTreeMap<&String, Option<&AccountInfo>> {
+        self.current
+            .iter()
+            .flat_map(|partial| partial.accounts.keys())
+            .map(|account| (account, self.identities.get(account)))
+            .collect()
+    }
+
+    /// Loads the accounts of the next transaction, once the previous one is applied to the root.
+    /// Fails when they are missing or do not match the root: the transaction then fails without
+    /// touching the root.
+    pub(crate) fn load_next_partial_state(&mut self) -> Result<(), WalletError> {
+        self.root = self.root();
+        self.current = None;
+        self.identities.clear();
+        let partial = self
+            .batch
+            .pop_front()
+            .ok_or(WalletError::MissingCommitmentMetadata)?;
+        if !partial.verify(self.root) {
+            return Err(WalletError::InvalidCommitmentMetadata);
+        }
+        self.identities = partial.existing_accounts();
+        self.current = Some(partial);
+        Ok(())
+    }
+
+    /// Drops the changes of the transaction being executed, including the migration of its
+    /// accounts: the root stays the one they were proven against
+    pub(crate) fn revert_partial_state(&mut self) {
+        if let Some(partial) = self.current.take() {
+            self.identities = partial.existing_accounts();
+        }
+    }
+
+    /// Fails when an account of `action` is not proven, as the new root could not be computed
+    pub(crate) fn check_touched_accounts(&self, action: &WalletAction) -> Result<(), WalletError> {
+        for account in action.touched_accounts() {
+            if !self
+                .current
+                .as_ref()
+                .is_some_and(|partial| partial.accounts.contains_key(account))
+            {
+                return Err(WalletError::AccountNotProven(account.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
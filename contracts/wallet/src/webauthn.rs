@@ -0,0 +1,105 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use sdk::Identity;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{WalletAction, WalletError};
+
+/// A WebAuthn assertion, sent in a [`WalletAction::WebAuthnAssertion`] blob and verified by the
+/// wallet contract itself. The P-256 signature covers `authenticator_data || sha256(client_data_json)`
+/// (hashed with SHA-256), which is the message signed by WebAuthn authenticators.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
+pub struct Secp256r1Blob {
+    pub identity: Identity,
+    /// SEC1 compressed P-256 public key
+    pub public_key: [u8; 33],
+    pub signature: [u8; 64],
+    pub authenticator_data: Vec<u8>,
+    pub client_data_json: String,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ty: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The challenge a passkey must sign to perform `action` (the borsh-encoded wallet action)
+/// on `account` at `nonce`. Binding it to the account nonce prevents an assertion from being replayed.
+pub fn challenge(account: &str, nonce: u128, action: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{account}:{nonce}:").as_bytes());
+    hasher.update(action);
+    hasher.finalize().into()
+}
+
+/// Checks that the transaction contains a WebAuthn assertion from `public_key`, made for `rp_id`
+/// from a page of `origin`, answering the challenge for `account` at `nonce`.
+pub fn verify(
+    calldata: &sdk::Calldata,
+    public_key: &str,
+    rp_id: &str,
+    origin: &str,
+    account: &str,
+    nonce: u128,
+) -> Result<(), WalletError> {
+    let wallet_blob = crate::wallet_blob(calldata)?;
+    let blob = calldata
+        .blobs
+        .iter()
+        .filter(|(_, b)| b.contract_name == wallet_blob.contract_name)
+        .find_map(
+            |(_, b)| match borsh::from_slice::<WalletAction>(&b.data.0) {
+                Ok(WalletAction::WebAuthnAssertion { assertion, .. }) => Some(assertion),
+                _ => None,
+            },
+        )
+        .ok_or(WalletError::MissingBlob("WebAuthn assertion".to_string()))?;
+
+    if blob.identity != calldata.identity {
+        return Err(WalletError::SignerIdentityMismatch);
+    }
+    if hex::encode(blob.public_key) != public_key {
+        return Err(WalletError::InvalidAuthentication);
+    }
+
+    let key = VerifyingKey::from_sec1_bytes(&blob.public_key)
+        .map_err(|_| WalletError::InvalidPublicKey)?;
+    let signature = Signature::try_from(blob.signature.as_slice())
+        .map_err(|_| WalletError::InvalidWebAuthnSignature)?;
+    let mut message = blob.authenticator_data.clone();
+    message.extend_from_slice(&Sha256::digest(blob.client_data_json.as_bytes()));
+    key.verify(&message, &signature)
+        .map_err(|_| WalletError::InvalidWebAuthnSignature)?;
+
+    // Flags byte follows the 32 bytes rpIdHash, bit 0 is "user present"
+    let (rp_id_hash, flags) = match blob.authenticator_data.get(..33) {
+        Some([rp_id_hash @ .., flags]) => (rp_id_hash, flags),
+        _ => return Err(WalletError::InvalidAuthenticatorData),
+    };
+    if rp_id_hash != Sha256::digest(rp_id.as_bytes()).as_slice() {
+        return Err(WalletError::InvalidRelyingParty);
+    }
+    if flags & 0x01 == 0 {
+        return Err(WalletError::UserPresenceNotAsserted);
+    }
+
+    let client_data: ClientData =
+        serde_json::from_str(&blob.client_data_json).map_err(|_| WalletError::InvalidClientData)?;
+    if client_data.ty != "webauthn.get" {
+        return Err(WalletError::InvalidClientDataType);
+    }
+    if client_data.origin != origin {
+        return Err(WalletError::InvalidOrigin);
+    }
+    let action = &wallet_blob.data.0;
+    if client_data.challenge != URL_SAFE_NO_PAD.encode(challenge(account, nonce, action)) {
+        return Err(WalletError::InvalidChallenge);
+    }
+
+    Ok(())
+}
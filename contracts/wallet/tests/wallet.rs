@@ -0,0 +1,1757 @@
+use std::collections::BTreeMap;
+
+use client_sdk::transaction_builder::TxExecutorHandler;
+use hyle_smt_token::SmtTokenAction;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use sdk::{
+    hyle_model_utils::TimestampMs, verifiers::Secp256k1Blob, Blob, BlobData, BlobIndex, Calldata,
+    ContractName, Identity, RegisterContractEffect, StateCommitment, TxContext, TxHash, ZkContract,
+};
+use sha2::{Digest, Sha256};
+use wallet::{
+    client::tx_executor_handler::WalletProvableState,
+    migration::{VersionedAccount, VersionedState, STATE_MAGIC},
+    smt::PartialState,
+    webauthn::Secp256r1Blob,
+    AuthMethod, Permission, SpendingLimit, TokenActionKind, Wallet, WalletAction, WalletError,
+    WalletOutput, WindowLimit, DEFAULT_AUTH_METHOD,
+};
+
+const ACCOUNT: &str = "bob";
+const SECRET: &[u8] = b"bob's password";
+const SESSION_KEY: [u8; 33] = [2; 33];
+
+fn wallet_cn() -> ContractName {
+    ContractName("wallet".to_string())
+}
+
+fn identity() -> Identity {
+    Identity(format!("{ACCOUNT}@wallet"))
+}
+
+fn check_secret_blob() -> Blob {
+    Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(SECRET.to_vec()),
+    }
+}
+
+fn secp256k1_blob(nonce: u128) -> Blob {
+    let data: [u8; 32] = Sha256::digest(nonce.to_string().as_bytes()).into();
+    let blob = Secp256k1Blob {
+        identity: identity(),
+        data,
+        public_key: SESSION_KEY,
+        signature: [0; 64],
+    };
+    Blob {
+        contract_name: ContractName("secp256k1".to_string()),
+        data: BlobData(borsh::to_vec(&blob).unwrap()),
+    }
+}
+
+/// Builds the calldata for the wallet blob, which is always the last blob of the transaction.
+fn calldata(identity: Identity, timestamp: u128, blobs: Vec<Blob>) -> Calldata {
+    Calldata {
+        identity,
+        index: BlobIndex(blobs.len() - 1),
+        tx_blob_count: blobs.len(),
+        blobs: blobs.into(),
+        tx_hash: TxHash::default(),
+        tx_ctx: Some(TxContext {
+            timestamp: TimestampMs(timestamp),
+            ..Default::default()
+        }),
+        private_input: vec![],
+    }
+}
+
+fn execute_at(
+    wallet: &mut WalletProvableState,
+    identity: Identity,
+    timestamp: u128,
+    blobs: Vec<Blob>,
+) -> Result<WalletOutput, WalletError> {
+    let output = wallet
+        .handle(&calldata(identity, timestamp, blobs))
+        .expect("handle");
+    if output.success {
+        Ok(WalletOutput::from_program_outputs(&output.program_outputs).expect("WalletOutput"))
+    } else {
+        Err(WalletError::from_program_outputs(&output.program_outputs).expect("WalletError"))
+    }
+}
+
+fn execute_as(
+    wallet: &mut WalletProvableState,
+    identity: Identity,
+    blobs: Vec<Blob>,
+) -> Result<WalletOutput, WalletError> {
+    execute_at(wallet, identity, 1_000, blobs)
+}
+
+fn execute(
+    wallet: &mut WalletProvableState,
+    blobs: Vec<Blob>,
+) -> Result<WalletOutput, WalletError> {
+    execute_as(wallet, identity(), blobs)
+}
+
+/// Returns a wallet where ACCOUNT is registered and owns SESSION_KEY.
+fn wallet_with_session_key() -> WalletProvableState {
+    wallet_with_restricted_session_key(vec![], vec![])
+}
+
+fn wallet_with_limited_session_key(spending_limits: Vec<SpendingLimit>) -> WalletProvableState {
+    wallet_with_restricted_session_key(vec![], spending_limits)
+}
+
+fn wallet_with_restricted_session_key(
+    permissions: Vec<Permission>,
+    spending_limits: Vec<SpendingLimit>,
+) -> WalletProvableState {
+    let mut wallet = WalletProvableState::default();
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::Password {
+            hash: hex::encode(SECRET),
+        },
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), register.as_blob(wallet_cn())],
+    )
+    .expect("registration");
+
+    let add_key = WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions,
+        spending_limits,
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), add_key.as_blob(wallet_cn())],
+    )
+    .expect("add session key");
+    wallet
+}
+
+fn use_session_key(
+    wallet: &mut WalletProvableState,
+    nonce: u128,
+) -> Result<WalletOutput, WalletError> {
+    let action = WalletAction::UseSessionKey {
+        account: ACCOUNT.to_string(),
+        nonce,
+    };
+    execute(
+        wallet,
+        vec![secp256k1_blob(nonce), action.as_blob(wallet_cn())],
+    )
+}
+
+#[test]
+fn session_key_accepts_increasing_nonces() {
+    let mut wallet = wallet_with_session_key();
+
+    assert!(use_session_key(&mut wallet, 1).is_ok());
+    assert!(use_session_key(&mut wallet, 2).is_ok());
+    assert!(use_session_key(&mut wallet, 100).is_ok());
+}
+
+#[test]
+fn session_key_rejects_replayed_nonce() {
+    let mut wallet = wallet_with_session_key();
+
+    assert!(use_session_key(&mut wallet, 5).is_ok());
+    assert_eq!(
+        use_session_key(&mut wallet, 5),
+        Err(WalletError::SessionKeyNonceAlreadyUsed)
+    );
+    assert_eq!(
+        use_session_key(&mut wallet, 4),
+        Err(WalletError::SessionKeyNonceAlreadyUsed)
+    );
+    assert!(use_session_key(&mut wallet, 6).is_ok());
+}
+
+#[test]
+fn session_key_rejects_zero_nonce() {
+    let mut wallet = wallet_with_session_key();
+
+    assert_eq!(
+        use_session_key(&mut wallet, 0),
+        Err(WalletError::SessionKeyNonceAlreadyUsed)
+    );
+}
+
+fn assert_invalid_identity(res: Result<WalletOutput, WalletError>, got: &str) {
+    assert_eq!(
+        res,
+        Err(WalletError::InvalidIdentity {
+            expected: format!("{ACCOUNT}@wallet"),
+            got: got.to_string(),
+        })
+    );
+}
+
+#[test]
+fn actions_reject_identity_of_another_account() {
+    let mut wallet = wallet_with_session_key();
+    let mallory = Identity("mallory@wallet".to_string());
+
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 10,
+    };
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            mallory.clone(),
+            vec![check_secret_blob(), verify.as_blob(wallet_cn())],
+        ),
+        "mallory@wallet",
+    );
+
+    let add_key = WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode([3u8; 33]),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions: vec![],
+        spending_limits: vec![],
+    };
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            mallory.clone(),
+            vec![check_secret_blob(), add_key.as_blob(wallet_cn())],
+        ),
+        "mallory@wallet",
+    );
+
+    let remove_key = WalletAction::RemoveSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+    };
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            mallory.clone(),
+            vec![check_secret_blob(), remove_key.as_blob(wallet_cn())],
+        ),
+        "mallory@wallet",
+    );
+
+    let use_key = WalletAction::UseSessionKey {
+        account: ACCOUNT.to_string(),
+        nonce: 1,
+    };
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            mallory,
+            vec![secp256k1_blob(1), use_key.as_blob(wallet_cn())],
+        ),
+        "mallory@wallet",
+    );
+}
+
+#[test]
+fn registration_rejects_mismatched_identity() {
+    let mut wallet = WalletProvableState::default();
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::Password {
+            hash: hex::encode(SECRET),
+        },
+    };
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            Identity("mallory@wallet".to_string()),
+            vec![check_secret_blob(), register.as_blob(wallet_cn())],
+        ),
+        "mallory@wallet",
+    );
+}
+
+#[test]
+fn actions_reject_identity_of_another_contract() {
+    let mut wallet = wallet_with_session_key();
+    let use_key = WalletAction::UseSessionKey {
+        account: ACCOUNT.to_string(),
+        nonce: 1,
+    };
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            Identity(format!("{ACCOUNT}@other_wallet")),
+            vec![secp256k1_blob(1), use_key.as_blob(wallet_cn())],
+        ),
+        "bob@other_wallet",
+    );
+    assert_invalid_identity(
+        execute_as(
+            &mut wallet,
+            Identity(ACCOUNT.to_string()),
+            vec![secp256k1_blob(1), use_key.as_blob(wallet_cn())],
+        ),
+        "bob",
+    );
+}
+
+const RP_ID: &str = "wallet.hyli.org";
+const ORIGIN: &str = "https://wallet.hyli.org";
+
+/// Deterministic P-256 passkey
+fn passkey(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32]).unwrap()
+}
+
+fn passkey_public_key(passkey: &SigningKey) -> [u8; 33] {
+    passkey
+        .verifying_key()
+        .to_encoded_point(true)
+        .as_bytes()
+        .try_into()
+        .unwrap()
+}
+
+fn passkey_auth_method(passkey: &SigningKey) -> AuthMethod {
+    AuthMethod::WebAuthn {
+        credential_id: "credential".to_string(),
+        public_key: hex::encode(passkey_public_key(passkey)),
+        rp_id: RP_ID.to_string(),
+        origin: ORIGIN.to_string(),
+    }
+}
+
+/// Signs the assertion as an authenticator does
+fn sign_assertion(passkey: &SigningKey, assertion: &mut Secp256r1Blob) {
+    let mut message = assertion.authenticator_data.clone();
+    message.extend_from_slice(&Sha256::digest(assertion.client_data_json.as_bytes()));
+    let signature: Signature = passkey.sign(&message);
+    assertion.signature = signature.as_ref().try_into().unwrap();
+}
+
+fn webauthn_assertion(nonce: u128, passkey: &SigningKey, action: &WalletAction) -> Secp256r1Blob {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    let action = borsh::to_vec(action).unwrap();
+    let challenge = URL_SAFE_NO_PAD.encode(wallet::webauthn::challenge(ACCOUNT, nonce, &action));
+    let mut authenticator_data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+    authenticator_data.extend_from_slice(&[0x01, 0, 0, 0, 0]);
+    let mut assertion = Secp256r1Blob {
+        identity: identity(),
+        public_key: passkey_public_key(passkey),
+        signature: [0; 64],
+        authenticator_data,
+        client_data_json: format!(
+            r#"{{"type":"webauthn.get","challenge":"{challenge}","origin":"{ORIGIN}"}}"#
+        ),
+    };
+    sign_assertion(passkey, &mut assertion);
+    assertion
+}
+
+fn assertion_blob(assertion: &Secp256r1Blob) -> Blob {
+    WalletAction::WebAuthnAssertion {
+        account: ACCOUNT.to_string(),
+        assertion: assertion.clone(),
+    }
+    .as_blob(wallet_cn())
+}
+
+fn webauthn_blob(nonce: u128, passkey: &SigningKey, action: &WalletAction) -> Blob {
+    assertion_blob(&webauthn_assertion(nonce, passkey, action))
+}
+
+#[test]
+fn webauthn_challenge_is_bound_to_account_nonce() {
+    let mut wallet = WalletProvableState::default();
+    let passkey = passkey(3);
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: passkey_auth_method(&passkey),
+    };
+    execute(
+        &mut wallet,
+        vec![
+            webauthn_blob(0, &passkey, &register),
+            register.as_blob(wallet_cn()),
+        ],
+    )
+    .expect("registration");
+
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 1,
+    };
+    // The registration assertion cannot be replayed for another action
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![
+                webauthn_blob(0, &passkey, &register),
+                verify.as_blob(wallet_cn()),
+            ],
+        ),
+        Err(WalletError::InvalidChallenge)
+    );
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![
+                webauthn_blob(0, &self::passkey(4), &verify),
+                verify.as_blob(wallet_cn()),
+            ],
+        ),
+        Err(WalletError::InvalidAuthentication)
+    );
+    execute(
+        &mut wallet,
+        vec![
+            webauthn_blob(0, &passkey, &verify),
+            verify.as_blob(wallet_cn()),
+        ],
+    )
+    .expect("verify identity");
+    assert_eq!(wallet.get_nonce(ACCOUNT), Ok(1));
+    assert_eq!(
+        wallet.get_nonce("unknown"),
+        Err(WalletError::IdentityNotFound)
+    );
+
+    // Once the nonce moved, the same assertion is stale
+    let verify_again = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 2,
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![
+                webauthn_blob(0, &passkey, &verify_again),
+                verify_again.as_blob(wallet_cn()),
+            ],
+        ),
+        Err(WalletError::InvalidChallenge)
+    );
+}
+
+#[test]
+fn webauthn_assertion_must_be_signed_by_the_passkey() {
+    let mut wallet = WalletProvableState::default();
+    let passkey = passkey(3);
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: passkey_auth_method(&passkey),
+    };
+    let mut assert_rejected = |assertion: Secp256r1Blob, error: WalletError| {
+        assert_eq!(
+            execute(
+                &mut wallet,
+                vec![assertion_blob(&assertion), register.as_blob(wallet_cn())],
+            ),
+            Err(error)
+        );
+    };
+
+    let mut unsigned = webauthn_assertion(0, &passkey, &register);
+    unsigned.signature = [0; 64];
+    assert_rejected(unsigned, WalletError::InvalidWebAuthnSignature);
+
+    let mut forged = webauthn_assertion(0, &passkey, &register);
+    sign_assertion(&self::passkey(4), &mut forged);
+    assert_rejected(forged, WalletError::InvalidWebAuthnSignature);
+
+    let mut tampered = webauthn_assertion(0, &passkey, &register);
+    tampered.authenticator_data[32] = 0x05;
+    assert_rejected(tampered, WalletError::InvalidWebAuthnSignature);
+
+    let mut other_rp = webauthn_assertion(0, &passkey, &register);
+    other_rp.authenticator_data[..32].copy_from_slice(&Sha256::digest(b"evil.example"));
+    sign_assertion(&passkey, &mut other_rp);
+    assert_rejected(other_rp, WalletError::InvalidRelyingParty);
+
+    let mut other_origin = webauthn_assertion(0, &passkey, &register);
+    other_origin.client_data_json = other_origin
+        .client_data_json
+        .replace(ORIGIN, "https://evil.example");
+    sign_assertion(&passkey, &mut other_origin);
+    assert_rejected(other_origin, WalletError::InvalidOrigin);
+
+    execute(
+        &mut wallet,
+        vec![
+            webauthn_blob(0, &passkey, &register),
+            register.as_blob(wallet_cn()),
+        ],
+    )
+    .expect("registration");
+}
+
+#[test]
+fn webauthn_assertion_is_checked_against_the_registered_relying_party() {
+    let mut wallet = WalletProvableState::default();
+    let passkey = passkey(3);
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::WebAuthn {
+            credential_id: "credential".to_string(),
+            public_key: hex::encode(passkey_public_key(&passkey)),
+            rp_id: "app.example".to_string(),
+            origin: "https://app.example".to_string(),
+        },
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![
+                webauthn_blob(0, &passkey, &register),
+                register.as_blob(wallet_cn()),
+            ],
+        ),
+        Err(WalletError::InvalidRelyingParty)
+    );
+
+    let mut assertion = webauthn_assertion(0, &passkey, &register);
+    assertion.authenticator_data[..32].copy_from_slice(&Sha256::digest(b"app.example"));
+    assertion.client_data_json = assertion
+        .client_data_json
+        .replace(ORIGIN, "https://app.example");
+    sign_assertion(&passkey, &mut assertion);
+    let blobs = vec![assertion_blob(&assertion), register.as_blob(wallet_cn())];
+
+    // The assertion blob settles as a wallet action that changes no account
+    let root = wallet.root();
+    let mut assertion_calldata = calldata(identity(), 1_000, blobs.clone());
+    assertion_calldata.index = BlobIndex(0);
+    let output = wallet.handle(&assertion_calldata).unwrap();
+    assert_eq!(
+        WalletOutput::from_program_outputs(&output.program_outputs),
+        Some(WalletOutput::WebAuthnAssertion)
+    );
+    assert_eq!(wallet.root(), root);
+
+    execute(&mut wallet, blobs).expect("registration");
+}
+
+/// Public key of the secp256k1 private key `1`, i.e. the generator point
+const ETH_PUBLIC_KEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const ETH_ADDRESS: &str = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+
+fn eip191_blob(action: &WalletAction, nonce: u128) -> Blob {
+    let message = wallet::ethereum::message(ACCOUNT, &borsh::to_vec(action).unwrap(), nonce);
+    let blob = Secp256k1Blob {
+        identity: identity(),
+        data: wallet::ethereum::eip191_hash(&message),
+        public_key: hex::decode(ETH_PUBLIC_KEY).unwrap().try_into().unwrap(),
+        signature: [0; 64],
+    };
+    Blob {
+        contract_name: ContractName("secp256k1".to_string()),
+        data: BlobData(borsh::to_vec(&blob).unwrap()),
+    }
+}
+
+#[test]
+fn ethereum_address_authenticates_wallet_actions() {
+    let mut wallet = WalletProvableState::default();
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::EthereumAddress {
+            address: ETH_ADDRESS.to_string(),
+        },
+    };
+    execute(
+        &mut wallet,
+        vec![eip191_blob(&register, 0), register.as_blob(wallet_cn())],
+    )
+    .expect("registration");
+
+    let add_key = WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions: vec![],
+        spending_limits: vec![],
+    };
+    // A signature for another action is rejected
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![eip191_blob(&register, 0), add_key.as_blob(wallet_cn())],
+        ),
+        Err(WalletError::InvalidSignedMessage)
+    );
+    execute(
+        &mut wallet,
+        vec![eip191_blob(&add_key, 0), add_key.as_blob(wallet_cn())],
+    )
+    .expect("add session key");
+
+    let remove_key = WalletAction::RemoveSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+    };
+    execute(
+        &mut wallet,
+        vec![eip191_blob(&remove_key, 1), remove_key.as_blob(wallet_cn())],
+    )
+    .expect("remove session key");
+    assert_eq!(wallet.get_nonce(ACCOUNT), Ok(2));
+}
+
+#[test]
+fn ethereum_address_rejects_other_signers() {
+    let mut wallet = WalletProvableState::default();
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::EthereumAddress {
+            address: "0x0000000000000000000000000000000000000001".to_string(),
+        },
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![eip191_blob(&register, 0), register.as_blob(wallet_cn())],
+        ),
+        Err(WalletError::InvalidAuthentication)
+    );
+}
+
+#[test]
+fn ethereum_signature_is_bound_to_the_whole_action() {
+    let mut wallet = WalletProvableState::default();
+    let register = WalletAction::RegisterIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::EthereumAddress {
+            address: ETH_ADDRESS.to_string(),
+        },
+    };
+    execute(
+        &mut wallet,
+        vec![eip191_blob(&register, 0), register.as_blob(wallet_cn())],
+    )
+    .expect("registration");
+
+    let add_key = |key: [u8; 33]| WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(key),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions: vec![],
+        spending_limits: vec![],
+    };
+    // The signature of an action can't be attached to another action with the same name
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![
+                eip191_blob(&add_key(SESSION_KEY), 0),
+                add_key([3; 33]).as_blob(wallet_cn()),
+            ],
+        ),
+        Err(WalletError::InvalidSignedMessage)
+    );
+
+    // Nor be used by another identity
+    let mut blob = eip191_blob(&add_key(SESSION_KEY), 0);
+    let mut signed = borsh::from_slice::<Secp256k1Blob>(&blob.data.0).unwrap();
+    signed.identity = Identity("mallory@wallet".to_string());
+    blob.data = BlobData(borsh::to_vec(&signed).unwrap());
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![blob, add_key(SESSION_KEY).as_blob(wallet_cn())],
+        ),
+        Err(WalletError::SignerIdentityMismatch)
+    );
+
+    execute(
+        &mut wallet,
+        vec![
+            eip191_blob(&add_key(SESSION_KEY), 0),
+            add_key(SESSION_KEY).as_blob(wallet_cn()),
+        ],
+    )
+    .expect("add session key");
+}
+
+#[test]
+fn any_registered_auth_method_authenticates() {
+    let mut wallet = wallet_with_session_key();
+    let passkey = passkey(3);
+
+    let add_passkey = WalletAction::AddAuthMethod {
+        account: ACCOUNT.to_string(),
+        label: "passkey".to_string(),
+        auth_method: passkey_auth_method(&passkey),
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), add_passkey.as_blob(wallet_cn())],
+    )
+    .expect("add passkey");
+    let nonce = wallet.get_nonce(ACCOUNT).unwrap();
+
+    let remove_password = WalletAction::RemoveAuthMethod {
+        account: ACCOUNT.to_string(),
+        label: wallet::DEFAULT_AUTH_METHOD.to_string(),
+    };
+    execute(
+        &mut wallet,
+        vec![
+            webauthn_blob(nonce, &passkey, &remove_password),
+            remove_password.as_blob(wallet_cn()),
+        ],
+    )
+    .expect("remove password with passkey");
+
+    // The password no longer authenticates the account
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 10,
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), verify.as_blob(wallet_cn())],
+    )
+    .expect_err("password was removed");
+}
+
+#[test]
+fn last_auth_method_cannot_be_removed() {
+    let mut wallet = wallet_with_session_key();
+
+    let remove_password = WalletAction::RemoveAuthMethod {
+        account: ACCOUNT.to_string(),
+        label: wallet::DEFAULT_AUTH_METHOD.to_string(),
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![check_secret_blob(), remove_password.as_blob(wallet_cn())],
+        ),
+        Err(WalletError::LastAuthMethod)
+    );
+}
+
+#[test]
+fn password_rotation_replaces_hash_and_bumps_nonce() {
+    let mut wallet = wallet_with_session_key();
+    let nonce = wallet.get_nonce(ACCOUNT).unwrap();
+    let new_secret = b"bob's new password";
+
+    let rotate = WalletAction::RotatePassword {
+        account: ACCOUNT.to_string(),
+        new_hash: hex::encode(new_secret),
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), rotate.as_blob(wallet_cn())],
+    )
+    .expect("rotate password");
+    assert_eq!(wallet.get_nonce(ACCOUNT), Ok(nonce + 1));
+
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 10,
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![check_secret_blob(), verify.as_blob(wallet_cn())],
+        ),
+        Err(WalletError::InvalidAuthentication)
+    );
+    let new_check_secret = Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(new_secret.to_vec()),
+    };
+    execute(
+        &mut wallet,
+        vec![new_check_secret, verify.as_blob(wallet_cn())],
+    )
+    .expect("verify with new password");
+}
+
+#[test]
+fn password_rotation_requires_current_password() {
+    let mut wallet = wallet_with_session_key();
+
+    let rotate = WalletAction::RotatePassword {
+        account: ACCOUNT.to_string(),
+        new_hash: hex::encode(b"mallory's password"),
+    };
+    let wrong_secret = Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(b"guess".to_vec()),
+    };
+    assert_eq!(
+        execute(&mut wallet, vec![wrong_secret, rotate.as_blob(wallet_cn())]),
+        Err(WalletError::InvalidAuthentication)
+    );
+}
+
+fn register_password_account(wallet: &mut WalletProvableState, account: &str) {
+    let register = WalletAction::RegisterIdentity {
+        account: account.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::Password {
+            hash: hex::encode(account),
+        },
+    };
+    execute_as(
+        wallet,
+        Identity(format!("{account}@wallet")),
+        vec![account_secret_blob(account), register.as_blob(wallet_cn())],
+    )
+    .expect("registration");
+}
+
+/// check_secret blob of accounts registered by `register_password_account`
+fn account_secret_blob(account: &str) -> Blob {
+    Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(account.as_bytes().to_vec()),
+    }
+}
+
+fn guardian_action(
+    wallet: &mut WalletProvableState,
+    guardian: &str,
+    timestamp: u128,
+    action: WalletAction,
+) -> Result<WalletOutput, WalletError> {
+    execute_at(
+        wallet,
+        Identity(format!("{guardian}@wallet")),
+        timestamp,
+        vec![account_secret_blob(guardian), action.as_blob(wallet_cn())],
+    )
+}
+
+/// Bob's account, guarded by alice, carol and dave with a threshold of 2 and a 5s delay
+fn wallet_with_guardians() -> WalletProvableState {
+    let mut wallet = wallet_with_session_key();
+    for guardian in ["alice", "carol", "dave"] {
+        register_password_account(&mut wallet, guardian);
+    }
+    let set_guardians = WalletAction::SetGuardians {
+        account: ACCOUNT.to_string(),
+        guardians: vec!["alice".into(), "carol".into(), "dave".into()],
+        threshold: 2,
+        delay: 5_000,
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), set_guardians.as_blob(wallet_cn())],
+    )
+    .expect("set guardians");
+    wallet
+}
+
+fn approve(guardian: &str) -> WalletAction {
+    WalletAction::ApproveRecovery {
+        account: ACCOUNT.to_string(),
+        guardian: guardian.to_string(),
+        new_auth_method: AuthMethod::Password {
+            hash: hex::encode(b"recovered"),
+        },
+    }
+}
+
+fn finalize(guardian: &str) -> WalletAction {
+    WalletAction::FinalizeRecovery {
+        account: ACCOUNT.to_string(),
+        guardian: guardian.to_string(),
+    }
+}
+
+#[test]
+fn guardians_recover_account_after_delay() {
+    let mut wallet = wallet_with_guardians();
+
+    guardian_action(&mut wallet, "alice", 1_000, approve("alice")).expect("alice approves");
+    assert_eq!(
+        guardian_action(&mut wallet, "alice", 1_000, finalize("alice")),
+        Err(WalletError::RecoveryThresholdNotReached)
+    );
+    guardian_action(&mut wallet, "carol", 2_000, approve("carol")).expect("carol approves");
+    assert_eq!(
+        guardian_action(&mut wallet, "alice", 6_999, finalize("alice")),
+        Err(WalletError::RecoveryDelayNotExpired)
+    );
+    guardian_action(&mut wallet, "alice", 7_000, finalize("alice")).expect("finalize");
+
+    // The old password is gone, the recovered one works
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 10,
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), verify.as_blob(wallet_cn())],
+    )
+    .expect_err("old password");
+    let recovered_secret = Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(b"recovered".to_vec()),
+    };
+    execute(
+        &mut wallet,
+        vec![recovered_secret, verify.as_blob(wallet_cn())],
+    )
+    .expect("recovered password");
+}
+
+#[test]
+fn owner_cancels_pending_recovery() {
+    let mut wallet = wallet_with_guardians();
+
+    guardian_action(&mut wallet, "alice", 1_000, approve("alice")).expect("alice approves");
+    guardian_action(&mut wallet, "carol", 1_000, approve("carol")).expect("carol approves");
+
+    let cancel = WalletAction::CancelRecovery {
+        account: ACCOUNT.to_string(),
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), cancel.as_blob(wallet_cn())],
+    )
+    .expect("cancel");
+    assert_eq!(
+        guardian_action(&mut wallet, "alice", 10_000, finalize("alice")),
+        Err(WalletError::NoPendingRecovery)
+    );
+}
+
+#[test]
+fn only_authenticated_guardians_approve_recovery() {
+    let mut wallet = wallet_with_guardians();
+    register_password_account(&mut wallet, "mallory");
+
+    assert_eq!(
+        guardian_action(&mut wallet, "mallory", 1_000, approve("mallory")),
+        Err(WalletError::NotAGuardian)
+    );
+    // A guardian cannot be impersonated without its credentials
+    assert_eq!(
+        execute_as(
+            &mut wallet,
+            Identity("alice@wallet".to_string()),
+            vec![
+                account_secret_blob("mallory"),
+                approve("alice").as_blob(wallet_cn())
+            ],
+        ),
+        Err(WalletError::InvalidAuthentication)
+    );
+    guardian_action(&mut wallet, "alice", 1_000, approve("alice")).expect("alice approves");
+    assert_eq!(
+        guardian_action(&mut wallet, "alice", 1_000, approve("alice")),
+        Err(WalletError::RecoveryAlreadyApproved)
+    );
+}
+
+#[test]
+fn conflicting_proposal_does_not_block_recovery() {
+    let mut wallet = wallet_with_guardians();
+    let propose = |guardian: &str, secret: &[u8]| WalletAction::ApproveRecovery {
+        account: ACCOUNT.to_string(),
+        guardian: guardian.to_string(),
+        new_auth_method: AuthMethod::Password {
+            hash: hex::encode(secret),
+        },
+    };
+
+    // Alice proposes her own password first, the others still can propose another one
+    guardian_action(&mut wallet, "alice", 1_000, propose("alice", b"alice's")).expect("alice");
+    guardian_action(&mut wallet, "carol", 1_000, approve("carol")).expect("carol approves");
+    guardian_action(&mut wallet, "dave", 1_000, propose("dave", b"other")).expect("dave");
+    assert_eq!(
+        guardian_action(&mut wallet, "alice", 1_000, finalize("alice")),
+        Err(WalletError::RecoveryThresholdNotReached)
+    );
+
+    // Dave replaces his approval, reaching the threshold
+    guardian_action(&mut wallet, "dave", 2_000, approve("dave")).expect("dave approves");
+    let pending = wallet.identities[ACCOUNT].pending_recovery.clone().unwrap();
+    assert_eq!(pending.executable_at, Some(TimestampMs(7_000)));
+    assert_eq!(pending.proposals.len(), 2);
+    assert_eq!(
+        pending.approved(2).map(|proposal| &proposal.approvals),
+        Some(&vec!["carol".to_string(), "dave".to_string()])
+    );
+
+    // The proposal that reached the threshold can't be changed anymore
+    assert_eq!(
+        guardian_action(&mut wallet, "dave", 2_000, propose("dave", b"alice's")),
+        Err(WalletError::RecoveryPending)
+    );
+
+    guardian_action(&mut wallet, "alice", 7_000, finalize("alice")).expect("finalize");
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 10,
+    };
+    let recovered_secret = Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(b"recovered".to_vec()),
+    };
+    execute(
+        &mut wallet,
+        vec![recovered_secret, verify.as_blob(wallet_cn())],
+    )
+    .expect("recovered password");
+}
+
+const RECOVERY_KEY: [u8; 33] = [5; 33];
+
+/// Secp256k1 blob of the recovery key, which signs the wallet action itself
+fn recovery_key_blob(action: &WalletAction) -> Blob {
+    let data: [u8; 32] = Sha256::digest(borsh::to_vec(action).unwrap()).into();
+    let blob = Secp256k1Blob {
+        identity: identity(),
+        data,
+        public_key: RECOVERY_KEY,
+        signature: [0; 64],
+    };
+    Blob {
+        contract_name: ContractName("secp256k1".to_string()),
+        data: BlobData(borsh::to_vec(&blob).unwrap()),
+    }
+}
+
+fn execute_signed_by_recovery_key(
+    wallet: &mut WalletProvableState,
+    timestamp: u128,
+    action: WalletAction,
+) -> Result<WalletOutput, WalletError> {
+    execute_at(
+        wallet,
+        identity(),
+        timestamp,
+        vec![recovery_key_blob(&action), action.as_blob(wallet_cn())],
+    )
+}
+
+/// Bob's account, with a recovery key and a 5s delay
+fn wallet_with_recovery_key() -> WalletProvableState {
+    let mut wallet = wallet_with_session_key();
+    let set_recovery_key = WalletAction::SetRecoveryKey {
+        account: ACCOUNT.to_string(),
+        recovery_key: Some(wallet::RecoveryKey {
+            public_key: hex::encode(RECOVERY_KEY),
+            delay: 5_000,
+        }),
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), set_recovery_key.as_blob(wallet_cn())],
+    )
+    .expect("set recovery key");
+    wallet
+}
+
+#[test]
+fn recovery_key_resets_auth_method_after_delay() {
+    let mut wallet = wallet_with_recovery_key();
+    let nonce = wallet.get_nonce(ACCOUNT).unwrap();
+
+    let initiate = WalletAction::InitiateKeyRecovery {
+        account: ACCOUNT.to_string(),
+        nonce,
+        new_auth_method: AuthMethod::Password {
+            hash: hex::encode(b"recovered"),
+        },
+    };
+    execute_signed_by_recovery_key(&mut wallet, 1_000, initiate.clone()).expect("initiate");
+    // The signature is bound to the nonce, it cannot be replayed
+    assert_eq!(
+        execute_signed_by_recovery_key(&mut wallet, 1_000, initiate),
+        Err(WalletError::InvalidNonce)
+    );
+
+    let finalize = WalletAction::FinalizeKeyRecovery {
+        account: ACCOUNT.to_string(),
+        nonce: nonce + 1,
+    };
+    assert_eq!(
+        execute_signed_by_recovery_key(&mut wallet, 5_999, finalize.clone()),
+        Err(WalletError::RecoveryDelayNotExpired)
+    );
+    execute_signed_by_recovery_key(&mut wallet, 6_000, finalize).expect("finalize");
+
+    let recovered_secret = Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(b"recovered".to_vec()),
+    };
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 10,
+    };
+    execute(
+        &mut wallet,
+        vec![recovered_secret, verify.as_blob(wallet_cn())],
+    )
+    .expect("recovered password");
+}
+
+#[test]
+fn owner_vetoes_recovery_key() {
+    let mut wallet = wallet_with_recovery_key();
+    let nonce = wallet.get_nonce(ACCOUNT).unwrap();
+
+    let initiate = WalletAction::InitiateKeyRecovery {
+        account: ACCOUNT.to_string(),
+        nonce,
+        new_auth_method: AuthMethod::Password {
+            hash: hex::encode(b"stolen"),
+        },
+    };
+    execute_signed_by_recovery_key(&mut wallet, 1_000, initiate).expect("initiate");
+
+    let cancel = WalletAction::CancelRecovery {
+        account: ACCOUNT.to_string(),
+    };
+    execute(
+        &mut wallet,
+        vec![check_secret_blob(), cancel.as_blob(wallet_cn())],
+    )
+    .expect("cancel");
+
+    let finalize = WalletAction::FinalizeKeyRecovery {
+        account: ACCOUNT.to_string(),
+        nonce: wallet.get_nonce(ACCOUNT).unwrap(),
+    };
+    assert_eq!(
+        execute_signed_by_recovery_key(&mut wallet, 10_000, finalize),
+        Err(WalletError::NoPendingRecovery)
+    );
+}
+
+fn token_blob(action: SmtTokenAction) -> Blob {
+    Blob {
+        contract_name: ContractName("oranj".to_string()),
+        data: BlobData(borsh::to_vec(&action).unwrap()),
+    }
+}
+
+fn transfer(amount: u128) -> Blob {
+    token_blob(SmtTokenAction::Transfer {
+        sender: identity(),
+        recipient: Identity("alice@wallet".to_string()),
+        amount,
+    })
+}
+
+fn use_session_key_with(
+    wallet: &mut WalletProvableState,
+    nonce: u128,
+    timestamp: u128,
+    token_blobs: Vec<Blob>,
+) -> Result<WalletOutput, WalletError> {
+    let action = WalletAction::UseSessionKey {
+        account: ACCOUNT.to_string(),
+        nonce,
+    };
+    let mut blobs = vec![secp256k1_blob(nonce)];
+    blobs.extend(token_blobs);
+    blobs.push(action.as_blob(wallet_cn()));
+    execute_at(wallet, identity(), timestamp, blobs)
+}
+
+#[test]
+fn session_key_enforces_per_transaction_limit() {
+    let mut wallet = wallet_with_limited_session_key(vec![SpendingLimit {
+        contract_name: ContractName("oranj".to_string()),
+        per_transaction: Some(100),
+        per_window: None,
+    }]);
+
+    assert!(use_session_key_with(&mut wallet, 1, 1_000, vec![transfer(100)]).is_ok());
+    assert_eq!(
+        use_session_key_with(&mut wallet, 2, 1_000, vec![transfer(60), transfer(41)]),
+        Err(WalletError::TransactionLimitExceeded(ContractName(
+            "oranj".to_string()
+        )))
+    );
+    let approve = token_blob(SmtTokenAction::Approve {
+        owner: identity(),
+        spender: Identity("alice@wallet".to_string()),
+        amount: 1_000,
+    });
+    assert_eq!(
+        use_session_key_with(&mut wallet, 3, 1_000, vec![approve]),
+        Err(WalletError::TransactionLimitExceeded(ContractName(
+            "oranj".to_string()
+        )))
+    );
+}
+
+#[test]
+fn session_key_enforces_rolling_window_limit() {
+    let mut wallet = wallet_with_limited_session_key(vec![SpendingLimit {
+        contract_name: ContractName("oranj".to_string()),
+        per_transaction: None,
+        per_window: Some(WindowLimit {
+            duration: 1_000,
+            amount: 100,
+        }),
+    }]);
+
+    assert!(use_session_key_with(&mut wallet, 1, 1_000, vec![transfer(60)]).is_ok());
+    assert_eq!(
+        use_session_key_with(&mut wallet, 2, 1_500, vec![transfer(60)]),
+        Err(WalletError::WindowLimitExceeded(ContractName(
+            "oranj".to_string()
+        )))
+    );
+    assert!(use_session_key_with(&mut wallet, 3, 1_500, vec![transfer(40)]).is_ok());
+    // The first transfer left the window
+    assert!(use_session_key_with(&mut wallet, 4, 2_000, vec![transfer(60)]).is_ok());
+    assert_eq!(
+        use_session_key_with(&mut wallet, 5, 2_400, vec![transfer(1)]),
+        Err(WalletError::WindowLimitExceeded(ContractName(
+            "oranj".to_string()
+        )))
+    );
+}
+
+fn transfer_to(recipient: &str, amount: u128) -> Blob {
+    token_blob(SmtTokenAction::Transfer {
+        sender: identity(),
+        recipient: Identity(recipient.to_string()),
+        amount,
+    })
+}
+
+#[test]
+fn session_key_enforces_action_permissions() {
+    let mut wallet = wallet_with_restricted_session_key(
+        vec![Permission {
+            contract_name: ContractName("oranj".to_string()),
+            actions: vec![TokenActionKind::Transfer],
+            recipients: Some(vec![Identity("alice@wallet".to_string())]),
+        }],
+        vec![],
+    );
+
+    assert!(
+        use_session_key_with(&mut wallet, 1, 1_000, vec![transfer_to("alice@wallet", 10)]).is_ok()
+    );
+    assert_eq!(
+        use_session_key_with(
+            &mut wallet,
+            2,
+            1_000,
+            vec![transfer_to("mallory@wallet", 10)]
+        ),
+        Err(WalletError::RecipientNotAllowed {
+            contract_name: ContractName("oranj".to_string()),
+            recipient: Identity("mallory@wallet".to_string()),
+        })
+    );
+    let approve = token_blob(SmtTokenAction::Approve {
+        owner: identity(),
+        spender: Identity("alice@wallet".to_string()),
+        amount: 10,
+    });
+    assert_eq!(
+        use_session_key_with(&mut wallet, 3, 1_000, vec![approve]),
+        Err(WalletError::ActionNotAllowed {
+            contract_name: ContractName("oranj".to_string()),
+            action: TokenActionKind::Approve,
+        })
+    );
+}
+
+fn register_blobs(account: &str) -> Vec<Blob> {
+    let register = WalletAction::RegisterIdentity {
+        account: account.to_string(),
+        nonce: 0,
+        auth_method: AuthMethod::Password {
+            hash: hex::encode(SECRET),
+        },
+    };
+    vec![check_secret_blob(), register.as_blob(wallet_cn())]
+}
+
+#[test]
+fn commitment_is_the_accounts_root() {
+    let mut wallet = WalletProvableState::default();
+    assert_eq!(Wallet::default().commit().0, wallet.root().to_vec());
+
+    execute(&mut wallet, register_blobs(ACCOUNT)).expect("registration");
+    assert_ne!(wallet.root(), [0; 32]);
+
+    // The indexer and prover persist the accounts only, the tree is rebuilt from them
+    let decoded: WalletProvableState = borsh::from_slice(&borsh::to_vec(&wallet).unwrap()).unwrap();
+    assert_eq!(decoded.root(), wallet.root());
+}
+
+#[test]
+fn failed_transaction_keeps_the_accounts_root() {
+    let mut wallet = wallet_with_session_key();
+    let root = wallet.root();
+
+    assert!(use_session_key(&mut wallet, 0).is_err());
+    assert_eq!(wallet.root(), root);
+
+    assert!(use_session_key(&mut wallet, 1).is_ok());
+    assert_ne!(wallet.root(), root);
+}
+
+#[test]
+fn batched_transactions_are_proven_against_the_committed_root() {
+    let mut state = WalletProvableState::default();
+    let initial_root = state.root();
+
+    let add_key = WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions: vec![],
+        spending_limits: vec![],
+    };
+    let txs = vec![
+        register_blobs(ACCOUNT),
+        vec![check_secret_blob(), add_key.as_blob(wallet_cn())],
+        register_blobs("alice"),
+    ];
+
+    // As the prover does: build the metadata of each transaction before handling it
+    let mut metadata: Option<Vec<u8>> = None;
+    let mut calldatas = vec![];
+    for (tx, account) in txs.into_iter().zip([ACCOUNT, ACCOUNT, "alice"]) {
+        let next = state.build_commitment_metadata(tx.last().unwrap()).unwrap();
+        metadata = Some(match metadata {
+            Some(initial) => state.merge_commitment_metadata(initial, next).unwrap(),
+            None => next,
+        });
+        let calldata = calldata(Identity(format!("{account}@wallet")), 1_000, tx);
+        assert!(state.handle(&calldata).unwrap().success);
+        calldatas.push(calldata);
+    }
+
+    // As the guest does: execute all of them on the merged metadata
+    let mut wallet: Wallet = borsh::from_slice(&metadata.unwrap()).unwrap();
+    assert_eq!(wallet.commit().0, initial_root.to_vec());
+    for calldata in &calldatas {
+        assert!(wallet.execute(calldata).is_ok());
+    }
+    assert_eq!(wallet.commit().0, state.root().to_vec());
+}
+
+#[test]
+fn committed_image_executes_like_the_sources() {
+    let mut state = WalletProvableState::default();
+    let add_key = WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions: vec![],
+        spending_limits: vec![],
+    };
+    let use_key = WalletAction::UseSessionKey {
+        account: ACCOUNT.to_string(),
+        nonce: 1,
+    };
+    let txs = vec![
+        register_blobs(ACCOUNT),
+        vec![check_secret_blob(), add_key.as_blob(wallet_cn())],
+        vec![secp256k1_blob(1), use_key.as_blob(wallet_cn())],
+    ];
+
+    let mut metadata: Option<Vec<u8>> = None;
+    let mut calldatas = vec![];
+    let mut expected = vec![];
+    for tx in txs {
+        let next = state.build_commitment_metadata(tx.last().unwrap()).unwrap();
+        metadata = Some(match metadata {
+            Some(initial) => state.merge_commitment_metadata(initial, next).unwrap(),
+            None => next,
+        });
+        let calldata = calldata(identity(), 1_000, tx);
+        let output = state.handle(&calldata).unwrap();
+        assert!(output.success);
+        expected.push(output);
+        calldatas.push(calldata);
+    }
+
+    // Same input encoding as the risc0 prover of the client SDK
+    let input = borsh::to_vec(&(metadata.unwrap(), calldatas)).unwrap();
+    let env = risc0_zkvm::ExecutorEnv::builder()
+        .write(&input.len())
+        .unwrap()
+        .write_slice(&input)
+        .build()
+        .unwrap();
+    let session = risc0_zkvm::default_executor()
+        .execute(env, wallet::client::metadata::WALLET_ELF)
+        .expect("The committed wallet.img failed to execute");
+    let outputs: Vec<sdk::HyleOutput> = borsh::from_slice(&session.journal.bytes).unwrap();
+
+    assert_eq!(outputs.len(), expected.len());
+    for (output, expected) in outputs.iter().zip(&expected) {
+        assert!(output.success, "{:?}", output.program_outputs);
+        assert_eq!(output.initial_state, expected.initial_state);
+        assert_eq!(output.next_state, expected.next_state);
+        assert_eq!(output.program_outputs, expected.program_outputs);
+    }
+}
+
+/// Error of a transaction executed by the guest
+fn guest_error(res: Result<impl std::fmt::Debug, String>) -> WalletError {
+    WalletError::from_program_outputs(res.expect_err("transaction should fail").as_bytes())
+        .expect("WalletError")
+}
+
+#[test]
+fn commitment_metadata_must_match_the_root() {
+    let state = wallet_with_session_key();
+
+    // Pretend the registered account does not exist
+    let forged = PartialState {
+        accounts: BTreeMap::from([(ACCOUNT.to_string(), None)]),
+        proof: vec![],
+    };
+    let mut wallet = Wallet::with_partial_states(state.root(), vec![forged]);
+    let res = wallet.execute(&calldata(identity(), 1_000, register_blobs(ACCOUNT)));
+    assert_eq!(guest_error(res), WalletError::InvalidCommitmentMetadata);
+    assert_eq!(wallet.commit().0, state.root().to_vec());
+
+    let res = wallet.execute(&calldata(identity(), 1_000, register_blobs(ACCOUNT)));
+    assert_eq!(guest_error(res), WalletError::MissingCommitmentMetadata);
+}
+
+#[test]
+fn commitment_metadata_must_prove_the_touched_accounts() {
+    let state = WalletProvableState::default();
+
+    let blobs = register_blobs("alice");
+    let metadata = state
+        .build_commitment_metadata(blobs.last().unwrap())
+        .unwrap();
+    let mut wallet: Wallet = borsh::from_slice(&metadata).unwrap();
+    let res = wallet.execute(&calldata(identity(), 1_000, register_blobs(ACCOUNT)));
+    assert_eq!(
+        guest_error(res),
+        WalletError::AccountNotProven(ACCOUNT.to_string())
+    );
+    assert_eq!(wallet.commit().0, state.root().to_vec());
+}
+
+#[test]
+fn undecodable_actions_fail_with_a_proof() {
+    let mut state = wallet_with_session_key();
+    let root = state.root();
+
+    let blob = Blob {
+        contract_name: wallet_cn(),
+        data: BlobData(vec![0xff; 3]),
+    };
+    let metadata = state.build_commitment_metadata(&blob).unwrap();
+    let mut wallet: Wallet = borsh::from_slice(&metadata).unwrap();
+    let calldata = calldata(identity(), 1_000, vec![blob]);
+    assert!(matches!(
+        guest_error(wallet.execute(&calldata)),
+        WalletError::Sdk(_)
+    ));
+    assert_eq!(wallet.commit().0, root.to_vec());
+
+    assert!(!state.handle(&calldata).unwrap().success);
+    assert_eq!(state.root(), root);
+}
+
+#[test]
+fn errors_are_encoded_in_program_outputs() {
+    let mut wallet = wallet_with_session_key();
+    let action = WalletAction::UseSessionKey {
+        account: ACCOUNT.to_string(),
+        nonce: 0,
+    };
+    let output = wallet
+        .handle(&calldata(
+            identity(),
+            1_000,
+            vec![secp256k1_blob(0), action.as_blob(wallet_cn())],
+        ))
+        .unwrap();
+    assert!(!output.success);
+
+    let error = WalletError::from_program_outputs(&output.program_outputs).unwrap();
+    assert_eq!(error, WalletError::SessionKeyNonceAlreadyUsed);
+    assert_eq!(error.code(), 303);
+    assert_eq!(error.to_string(), "Session key nonce already used");
+}
+
+#[test]
+fn successful_actions_return_typed_outputs() {
+    let mut wallet = WalletProvableState::default();
+    assert_eq!(
+        execute(&mut wallet, register_blobs(ACCOUNT)),
+        Ok(WalletOutput::Registered)
+    );
+
+    let add_key = WalletAction::AddSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+        expiration_date: 10_000,
+        whitelist: None,
+        lane_id: None,
+        permissions: vec![],
+        spending_limits: vec![],
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![check_secret_blob(), add_key.as_blob(wallet_cn())]
+        ),
+        Ok(WalletOutput::SessionKeyAdded {
+            key: hex::encode(SESSION_KEY),
+            expiration: 10_000,
+        })
+    );
+    assert_eq!(
+        use_session_key(&mut wallet, 1),
+        Ok(WalletOutput::SessionKeyUsed {
+            key: hex::encode(SESSION_KEY),
+            nonce: 1,
+        })
+    );
+
+    let remove_key = WalletAction::RemoveSessionKey {
+        account: ACCOUNT.to_string(),
+        key: hex::encode(SESSION_KEY),
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![check_secret_blob(), remove_key.as_blob(wallet_cn())]
+        ),
+        Ok(WalletOutput::SessionKeyRemoved)
+    );
+
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 5,
+    };
+    assert_eq!(
+        execute(
+            &mut wallet,
+            vec![check_secret_blob(), verify.as_blob(wallet_cn())]
+        ),
+        Ok(WalletOutput::Verified { nonce: 5 })
+    );
+}
+
+/// State of the first wallet, whose whole state was committed on-chain: ACCOUNT with nonce 3,
+/// a password and SESSION_KEY, whitelisted for "hyllar"
+const LEGACY_STATE: &str = concat!(
+    "0100000003000000626f62001c000000363236663632323737333230373036313733373337373666373236",
+    "340100000042000000303230323032303230323032303230323032303230323032303230323032303230323032",
+    "303230323032303230323032303230323032303230323032303230323032303230323032102700000000000000",
+    "000000000000000003000000000000000000000000000000010100000006000000",
+    "68796c6c61720003000000000000000000000000000000",
+);
+
+#[test]
+fn legacy_state_is_migrated_on_decode() {
+    let wallet: WalletProvableState =
+        borsh::from_slice(&hex::decode(LEGACY_STATE).unwrap()).unwrap();
+
+    let info = wallet.identities.get(ACCOUNT).unwrap();
+    assert_eq!(info.nonce, 3);
+    assert_eq!(
+        info.auth_methods.get(DEFAULT_AUTH_METHOD),
+        Some(&AuthMethod::Password {
+            hash: hex::encode(SECRET)
+        })
+    );
+    assert!(info.guardians.is_none());
+    assert_eq!(info.session_keys.len(), 1);
+    let key = &info.session_keys[0];
+    assert_eq!(key.public_key, hex::encode(SESSION_KEY));
+    assert_eq!(key.expiration_date, TimestampMs(10_000));
+    assert_eq!(
+        key.whitelist,
+        Some(vec![ContractName("hyllar".to_string())])
+    );
+    assert!(key.permissions.is_empty() && key.spending_limits.is_empty());
+
+    // The account stays in its version until a transaction touches it
+    let bytes = borsh::to_vec(&wallet).unwrap();
+    assert_eq!(bytes[..4], STATE_MAGIC);
+    match VersionedState::decode(&bytes).unwrap() {
+        VersionedState::V1(accounts) => {
+            assert!(matches!(
+                accounts.get(ACCOUNT),
+                Some(VersionedAccount::V0(_))
+            ))
+        }
+        state => panic!("Unexpected snapshot {state:?}"),
+    }
+    let decoded: WalletProvableState = borsh::from_slice(&bytes).unwrap();
+    assert_eq!(decoded.root(), wallet.root());
+    assert_eq!(decoded.identities, wallet.identities);
+
+    // Persisted along other fields, e.g. by the indexer
+    let legacy = hex::decode(LEGACY_STATE).unwrap();
+    for bytes in [legacy, bytes] {
+        let nested = [bytes, borsh::to_vec("wallet").unwrap()].concat();
+        let (decoded, name): (WalletProvableState, String) = borsh::from_slice(&nested).unwrap();
+        assert_eq!(decoded.root(), wallet.root());
+        assert_eq!(name, "wallet");
+    }
+}
+
+#[test]
+fn legacy_accounts_are_migrated_by_the_contract() {
+    let mut state: WalletProvableState =
+        borsh::from_slice(&hex::decode(LEGACY_STATE).unwrap()).unwrap();
+    let legacy_root = state.root();
+
+    // A failed transaction does not migrate the account
+    assert_eq!(
+        use_session_key(&mut state, 3),
+        Err(WalletError::SessionKeyNonceAlreadyUsed)
+    );
+    assert_eq!(state.root(), legacy_root);
+
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 4,
+    };
+    let blobs = vec![check_secret_blob(), verify.as_blob(wallet_cn())];
+    let metadata = state
+        .build_commitment_metadata(blobs.last().unwrap())
+        .unwrap();
+    let calldata = calldata(identity(), 1_000, blobs);
+    assert!(state.handle(&calldata).unwrap().success);
+
+    // As the guest does: the legacy leaf is proven, then written in the latest version
+    let mut wallet: Wallet = borsh::from_slice(&metadata).unwrap();
+    assert_eq!(wallet.commit().0, legacy_root.to_vec());
+    assert!(wallet.execute(&calldata).is_ok());
+    assert_eq!(wallet.commit().0, state.root().to_vec());
+
+    match state.snapshot() {
+        VersionedState::V1(accounts) => {
+            assert!(
+                matches!(accounts.get(ACCOUNT), Some(VersionedAccount::V1(info)) if info.nonce == 4)
+            )
+        }
+        state => panic!("Unexpected snapshot {state:?}"),
+    }
+}
+
+#[test]
+fn cloned_state_is_independent() {
+    let mut wallet: WalletProvableState =
+        borsh::from_slice(&hex::decode(LEGACY_STATE).unwrap()).unwrap();
+    let mut cloned = wallet.clone();
+    assert_eq!(cloned.root(), wallet.root());
+
+    use_session_key(&mut cloned, 4).expect("use session key");
+    assert_ne!(cloned.root(), wallet.root());
+    assert_eq!(wallet.get_nonce(ACCOUNT), Ok(3));
+
+    // Both trees still prove their accounts
+    use_session_key(&mut wallet, 4).expect("use session key");
+    assert_eq!(cloned.root(), wallet.root());
+}
+
+#[test]
+fn legacy_commitment_is_upgraded_to_the_accounts_root() {
+    // The first wallet committed its whole state on-chain
+    let commitment = hex::decode(LEGACY_STATE).unwrap();
+    let upgraded = WalletProvableState::upgraded(&commitment, || unreachable!()).unwrap();
+
+    // Indexers and provers construct their state from the registration metadata
+    let register = RegisterContractEffect {
+        state_commitment: StateCommitment(upgraded.root().to_vec()),
+        contract_name: wallet_cn(),
+        ..Default::default()
+    };
+    let mut state =
+        WalletProvableState::construct_state(&register, &Some(borsh::to_vec(&upgraded).unwrap()))
+            .unwrap();
+    assert_eq!(state.root(), upgraded.root());
+
+    // The first proof starts from the registered root
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 4,
+    };
+    let blobs = vec![check_secret_blob(), verify.as_blob(wallet_cn())];
+    let metadata = state
+        .build_commitment_metadata(blobs.last().unwrap())
+        .unwrap();
+    let calldata = calldata(identity(), 1_000, blobs);
+    let output = state.handle(&calldata).unwrap();
+    assert!(output.success);
+    assert_eq!(output.initial_state, register.state_commitment);
+
+    let mut wallet: Wallet = borsh::from_slice(&metadata).unwrap();
+    assert_eq!(wallet.commit(), register.state_commitment);
+    assert!(wallet.execute(&calldata).is_ok());
+    assert_eq!(wallet.commit(), output.next_state);
+}
+
+#[test]
+fn accounts_root_is_upgraded_with_the_indexed_accounts() {
+    // First upgrade, then a transaction on the new program
+    let commitment = hex::decode(LEGACY_STATE).unwrap();
+    let mut indexed = WalletProvableState::upgraded(&commitment, || unreachable!()).unwrap();
+    let verify = WalletAction::VerifyIdentity {
+        account: ACCOUNT.to_string(),
+        nonce: 4,
+    };
+    execute(
+        &mut indexed,
+        vec![check_secret_blob(), verify.as_blob(wallet_cn())],
+    )
+    .expect("verify identity");
+    let root = indexed.root();
+
+    // Second upgrade: the root is kept, registered with the indexed accounts
+    let upgraded = WalletProvableState::upgraded(&root, || Ok(indexed.clone())).unwrap();
+    let register = RegisterContractEffect {
+        state_commitment: StateCommitment(root.to_vec()),
+        contract_name: wallet_cn(),
+        ..Default::default()
+    };
+    let state =
+        WalletProvableState::construct_state(&register, &Some(borsh::to_vec(&upgraded).unwrap()))
+            .unwrap();
+    assert_eq!(state.root(), root);
+    assert_eq!(state.identities, indexed.identities);
+
+    // Accounts that are not the on-chain ones are refused
+    assert!(WalletProvableState::upgraded(&root, || Ok(WalletProvableState::default())).is_err());
+}
@@ -50,6 +50,15 @@ export const SessionKeys = () => {
         fetchSessionKeys();
     }, [wallet.username]);
 
+    const hasSessionKey = async (publicKey: string) => {
+        try {
+            const accountInfo = await indexerService.getAccountInfo(wallet.username);
+            return accountInfo.session_keys.some((key: SessionKey) => key.key === publicKey);
+        } catch {
+            return false;
+        }
+    };
+
     const handleWalletEvent = (event: WalletEvent) => {
         // TODO: Make properly typed events
         const eventText = event.message;
@@ -94,22 +103,19 @@ export const SessionKeys = () => {
                 handleWalletEvent,
                 handleError
             );
-
-            await new Promise<void>((resolve, reject) => {
-                const timeout = setTimeout(() => {
-                    webSocketService.unsubscribeFromWalletEvents();
-                    reject(new Error("Operation timed out"));
-                }, 30000);
-
-                const unsubscribe = webSocketService.subscribeToWalletEvents((wsEvent) => {
-                    if (wsEvent.event === "Session key added") {
-                        localStorage.setItem(sessionKey.publicKey, sessionKey.privateKey);
-                        clearTimeout(timeout);
-                        unsubscribe();
-                        resolve();
-                    }
-                });
-            });
+            // Kept before the confirmation, which this device may not be subscribed to hear about
+            localStorage.setItem(sessionKey.publicKey, sessionKey.privateKey);
+
+            // Confirmed once the indexer executed the transaction
+            const deadline = Date.now() + 30000;
+            while (!(await hasSessionKey(sessionKey.publicKey))) {
+                if (Date.now() > deadline) {
+                    throw new Error("Operation timed out");
+                }
+                await new Promise((resolve) => setTimeout(resolve, 1000));
+            }
+            // The new key can subscribe this device to the account's events
+            webSocketService.authenticate();
 
             setStatus("Session key added successfully");
             setTimeout(() => setStatus(""), 3000);
@@ -139,7 +145,7 @@ export const SessionKeys = () => {
 
                 webSocketService.connect(wallet.address);
                 const unsubscribe = webSocketService.subscribeToWalletEvents((wsEvent) => {
-                    if (wsEvent.event === "Session key removed") {
+                    if (wsEvent.event.status === "Success" && wsEvent.event.output.type === "SessionKeyRemoved") {
                         localStorage.removeItem(publicKey);
                         clearTimeout(timeout);
                         unsubscribe();
@@ -211,7 +217,7 @@ export const SessionKeys = () => {
 
                 webSocketService.connect(wallet.address);
                 const unsubscribe = webSocketService.subscribeToWalletEvents((wsEvent) => {
-                    if (wsEvent.event === "Session key is valid") {
+                    if (wsEvent.event.status === "Success" && wsEvent.event.output.type === "SessionKeyUsed") {
                         clearTimeout(timeout);
                         unsubscribe();
                         webSocketService.disconnect();
@@ -255,7 +261,7 @@ export const SessionKeys = () => {
 
                 webSocketService.connect(wallet.address);
                 const unsubscribe = webSocketService.subscribeToWalletEvents((wsEvent) => {
-                    if (wsEvent.event === "Session key is valid") {
+                    if (wsEvent.event.status === "Success" && wsEvent.event.output.type === "SessionKeyUsed") {
                         clearTimeout(timeout);
                         unsubscribe();
                         webSocketService.disconnect();
@@ -20,7 +20,7 @@ interface SessionKey {
 
 interface AccountInfo {
     account: string;
-    auth_method: AuthMethod;
+    auth_methods: Record<string, AuthMethod>;
     session_keys: SessionKey[];
     nonce: number;
 }
@@ -50,7 +50,7 @@ class IndexerService {
     async getTransactionHistory(address: string): Promise<Transaction[]> {
         try {
             const response = await this.server.get<TransactionHistoryResponse>(
-                `v1/indexer/contract/oranj/history/${address}`,
+                `history/${address}`,
                 "Fetching transaction history"
             );
             return response.history;
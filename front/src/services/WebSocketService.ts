@@ -1,5 +1,34 @@
+import { ec as EC } from "elliptic";
+import SHA256 from "crypto-js/sha256";
+import { indexerService } from "./IndexerService";
+
 export type Transaction = any;
 
+// Output of a successful wallet action
+export type WalletOutput =
+    | { type: "Registered" }
+    | { type: "Verified"; nonce: number }
+    | { type: "SessionKeyAdded"; key: string; expiration: number }
+    | { type: "SessionKeyRemoved" }
+    | { type: "SessionKeyUsed"; key: string; nonce: number }
+    | { type: "AuthMethodAdded"; label: string }
+    | { type: "AuthMethodRemoved"; label: string }
+    | { type: "PasswordChanged" }
+    | { type: "GuardiansUpdated" }
+    | { type: "RecoveryApproved"; executable_at: number | null }
+    | { type: "RecoveryKeyUpdated" }
+    | { type: "RecoveryInitiated"; executable_at: number }
+    | { type: "RecoveryCancelled" }
+    | { type: "AccountRecovered" }
+    | { type: "WebAuthnAssertion" };
+
+// `code` is stable, see `WalletError::code` in the contract
+export type WalletOutcome =
+    | { status: "Success"; output: WalletOutput }
+    | { status: "Error"; code: number; message: string; error: unknown }
+    | { status: "Failed" }
+    | { status: "TimedOut" };
+
 export interface AppEvent {
     TxEvent: {
         account: string;
@@ -7,7 +36,7 @@ export interface AppEvent {
     };
     WalletEvent: {
         account: string;
-        event: string;
+        event: WalletOutcome;
     };
 }
 
@@ -15,6 +44,20 @@ interface RegisterTopicMessage {
     RegisterTopic: string;
 }
 
+// Replies to the commands, see `WsReply` and `WsError` in the server
+interface ResponseMessage {
+    Response: {
+        request_id: number;
+        result: { Ok: { Challenge?: { challenge: string } } } | { Err: { type: string } };
+    };
+}
+
+const CHALLENGE_REQUEST_ID = 0;
+const SUBSCRIBE_REQUEST_ID = 1;
+const PING_REQUEST_ID = 2;
+// The server forgets the subscriptions of a connection silent for 90s
+const PING_INTERVAL = 30000;
+
 type TxEventCallback = (event: AppEvent["TxEvent"]) => void;
 type WalletEventCallback = (event: AppEvent["WalletEvent"]) => void;
 
@@ -26,6 +69,9 @@ export class WebSocketService {
     private maxReconnectAttempts: number = 5;
     private reconnectTimeout: number = 1000;
     private currentAccount: string | null = null;
+    // Topic of the replies and of the events of the subscribed account, only known to this connection
+    private clientId: string = crypto.randomUUID();
+    private pingInterval: ReturnType<typeof setInterval> | null = null;
 
     constructor() {}
 
@@ -45,21 +91,31 @@ export class WebSocketService {
         this.ws.onopen = () => {
             console.log("WebSocket connected");
             this.reconnectAttempts = 0;
+            // A client id belongs to the connection that first used it
+            this.clientId = crypto.randomUUID();
             // Send registration message
             const registerMessage: RegisterTopicMessage = {
-                RegisterTopic: account,
+                RegisterTopic: this.clientId,
             };
             this.ws?.send(JSON.stringify(registerMessage));
+            this.authenticate();
+            this.stopPing();
+            this.pingInterval = setInterval(() => this.sendCommand(PING_REQUEST_ID, "Ping"), PING_INTERVAL);
         };
 
         this.ws.onmessage = (event) => {
             try {
-                const data: AppEvent = JSON.parse(event.data);
-                if (data.TxEvent) {
-                    this.txEventCallbacks.forEach((callback) => callback(data.TxEvent));
+                const data = JSON.parse(event.data);
+                if (data.Response) {
+                    this.handleResponse(data as ResponseMessage);
+                    return;
+                }
+                const appEvent: AppEvent = data;
+                if (appEvent.TxEvent) {
+                    this.txEventCallbacks.forEach((callback) => callback(appEvent.TxEvent));
                 }
-                if (data.WalletEvent) {
-                    this.walletEventCallbacks.forEach((callback) => callback(data.WalletEvent));
+                if (appEvent.WalletEvent) {
+                    this.walletEventCallbacks.forEach((callback) => callback(appEvent.WalletEvent));
                 }
             } catch (error) {
                 console.error("Error parsing WebSocket message:", error);
@@ -68,6 +124,7 @@ export class WebSocketService {
 
         this.ws.onclose = () => {
             console.log("WebSocket disconnected");
+            this.stopPing();
             this.handleReconnect();
         };
 
@@ -76,6 +133,54 @@ export class WebSocketService {
         };
     }
 
+    // The account's events are only sent once subscribed with one of its session keys,
+    // call again once a session key was added on this device
+    authenticate() {
+        this.sendCommand(CHALLENGE_REQUEST_ID, "Challenge");
+    }
+
+    private stopPing() {
+        if (this.pingInterval) {
+            clearInterval(this.pingInterval);
+            this.pingInterval = null;
+        }
+    }
+
+    private sendCommand(requestId: number, request: unknown) {
+        if (this.ws?.readyState !== WebSocket.OPEN) {
+            return;
+        }
+        this.ws.send(JSON.stringify({ Message: { client_id: this.clientId, request_id: requestId, request } }));
+    }
+
+    private async handleResponse({ Response: response }: ResponseMessage) {
+        if ("Err" in response.result) {
+            console.error("WebSocket command failed:", response.result.Err);
+            return;
+        }
+        const challenge = response.result.Ok.Challenge?.challenge;
+        if (response.request_id !== CHALLENGE_REQUEST_ID || !challenge || !this.currentAccount) {
+            return;
+        }
+        const account = this.currentAccount.split("@")[0];
+        const { session_keys } = await indexerService.getAccountInfo(account);
+        const key = session_keys.find((key) => key.expiration_date > Date.now() && localStorage.getItem(key.key));
+        if (!key) {
+            console.warn("No session key on this device, only public events will be received");
+            return;
+        }
+        const signature = new EC("secp256k1")
+            .keyFromPrivate(localStorage.getItem(key.key)!, "hex")
+            .sign(SHA256(challenge).toString(), { canonical: true });
+        this.sendCommand(SUBSCRIBE_REQUEST_ID, {
+            Subscribe: {
+                account,
+                public_key: key.key,
+                signature: signature.r.toString(16, 64) + signature.s.toString(16, 64),
+            },
+        });
+    }
+
     private handleReconnect() {
         if (this.reconnectAttempts < this.maxReconnectAttempts && this.currentAccount) {
             this.reconnectAttempts++;
@@ -109,6 +214,7 @@ export class WebSocketService {
     }
 
     disconnect() {
+        this.stopPing();
         if (this.ws) {
             this.ws.close();
             this.ws = null;
@@ -7,6 +7,8 @@ export type {
     WalletAction,
     WalletEventCallback,
     WalletEvent,
+    WalletOutcome,
+    WalletOutput,
     LoginStage,
     RegistrationStage,
 } from "./types/wallet";
@@ -64,7 +64,8 @@ export class PasswordAuthProvider implements AuthProvider {
             onWalletEvent?.({ account: identity, type: "checking_password", message: `Checking password for log in` });
 
             const userAccountInfo = await indexerService.getAccountInfo(username);
-            let storedHash = userAccountInfo.auth_method.Password.hash;
+            let storedHash = Object.values(userAccountInfo.auth_methods).find((method) => "Password" in method)
+                ?.Password.hash;
 
             const hashed_password_bytes = await sha256(stringToBytes(password));
             let encoder = new TextEncoder();
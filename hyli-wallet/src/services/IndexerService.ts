@@ -10,7 +10,7 @@ interface SessionKey {
 
 interface AccountInfo {
   account: string;
-  auth_method: AuthMethod;
+  auth_methods: Record<string, AuthMethod>;
   session_keys: SessionKey[];
   nonce: number;
 }
@@ -1,4 +1,4 @@
-import { OnchainWalletEventCallback } from "../types/wallet";
+import { OnchainWalletEventCallback, WalletOutcome } from "../types/wallet";
 import { ConfigService } from "./ConfigService";
 
 interface RegisterTopicMessage {
@@ -21,7 +21,7 @@ interface AppEvent {
     };
     WalletEvent: {
         account: string;
-        event: string;
+        event: WalletOutcome;
     };
 }
 
@@ -32,6 +32,18 @@ export type Secp256k1Blob = {
 
 export type AuthMethod = { Password: { hash: string } };
 
+export type Permission = {
+    contract_name: string;
+    actions: ({ Transfer: {} } | { TransferFrom: {} } | { Approve: {} })[];
+    recipients?: string[];
+};
+
+export type SpendingLimit = {
+    contract_name: string;
+    per_transaction?: number;
+    per_window?: { duration: number; amount: number };
+};
+
 export type WalletAction =
     | {
           RegisterIdentity: {
@@ -53,6 +65,8 @@ export type WalletAction =
               expiration_date: number;
               whitelist?: string[];
               laneId?: string;
+              permissions: Permission[];
+              spending_limits: SpendingLimit[];
           };
       }
     | {
@@ -72,7 +86,33 @@ export type WalletAction =
 export type TransactionCallback = (txHash: string, type: string) => void;
 export type WalletErrorCallback = (error: Error) => void;
 export type WalletEventCallback = (event: WalletEvent) => void;
-export type OnchainWalletEventCallback = (event: { event: string }) => void;
+export type OnchainWalletEventCallback = (event: { account: string; event: WalletOutcome }) => void;
+
+// Output of a successful wallet action
+export type WalletOutput =
+    | { type: "Registered" }
+    | { type: "Verified"; nonce: number }
+    | { type: "SessionKeyAdded"; key: string; expiration: number }
+    | { type: "SessionKeyRemoved" }
+    | { type: "SessionKeyUsed"; key: string; nonce: number }
+    | { type: "AuthMethodAdded"; label: string }
+    | { type: "AuthMethodRemoved"; label: string }
+    | { type: "PasswordChanged" }
+    | { type: "GuardiansUpdated" }
+    | { type: "RecoveryApproved"; executable_at: number | null }
+    | { type: "RecoveryKeyUpdated" }
+    | { type: "RecoveryInitiated"; executable_at: number }
+    | { type: "RecoveryCancelled" }
+    | { type: "AccountRecovered" }
+    | { type: "WebAuthnAssertion" };
+
+// Outcome of a wallet transaction, sent by the server over WebSocket
+export type WalletOutcome =
+    | { status: "Success"; output: WalletOutput }
+    // `code` is stable, see `WalletError::code` in the contract
+    | { status: "Error"; code: number; message: string; error: unknown }
+    | { status: "Failed" }
+    | { status: "TimedOut" };
 
 export type LoginStage = "checking_password";
 export type RegistrationStage = "sending_blob" | "blob_sent" | "sending_proof" | "proof_sent";
@@ -114,9 +154,17 @@ export const verifyIdentityBlob = (account: string, nonce: number): Blob => {
     return blob;
 };
 
-export const addSessionKeyBlob = (account: string, key: string, expiration_date: number, whitelist?: string[], laneId?: string): Blob => {
+export const addSessionKeyBlob = (
+    account: string,
+    key: string,
+    expiration_date: number,
+    whitelist?: string[],
+    laneId?: string,
+    permissions: Permission[] = [],
+    spending_limits: SpendingLimit[] = []
+): Blob => {
     const action: WalletAction = {
-        AddSessionKey: { account, key, expiration_date, whitelist, laneId },
+        AddSessionKey: { account, key, expiration_date, whitelist, laneId, permissions, spending_limits },
     };
     const blob: Blob = {
         contract_name: walletContractName,
@@ -194,6 +242,31 @@ const schema = BorshSchema.Enum({
         expiration_date: BorshSchema.u128,
         whitelist: BorshSchema.Option(BorshSchema.Vec(BorshSchema.String)),
         lane_id: BorshSchema.Option(BorshSchema.String),
+        permissions: BorshSchema.Vec(
+            BorshSchema.Struct({
+                contract_name: BorshSchema.String,
+                actions: BorshSchema.Vec(
+                    BorshSchema.Enum({
+                        Transfer: BorshSchema.Unit,
+                        TransferFrom: BorshSchema.Unit,
+                        Approve: BorshSchema.Unit,
+                    })
+                ),
+                recipients: BorshSchema.Option(BorshSchema.Vec(BorshSchema.String)),
+            })
+        ),
+        spending_limits: BorshSchema.Vec(
+            BorshSchema.Struct({
+                contract_name: BorshSchema.String,
+                per_transaction: BorshSchema.Option(BorshSchema.u128),
+                per_window: BorshSchema.Option(
+                    BorshSchema.Struct({
+                        duration: BorshSchema.u128,
+                        amount: BorshSchema.u128,
+                    })
+                ),
+            })
+        ),
     }),
     RemoveSessionKey: BorshSchema.Struct({
         account: BorshSchema.String,
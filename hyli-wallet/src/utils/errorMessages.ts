@@ -4,7 +4,28 @@ interface ErrorDetails {
     showDetails?: boolean;
 }
 
+// Errors of the wallet contract, by their stable code (see `WalletError::code` in the contract)
+const contractErrorMessages: Record<number, string> = {
+    201: "This username is already taken. Please choose a different one.",
+    204: "The password you entered is incorrect. Please try again.",
+    301: "This session key has expired or is no longer valid. Please create a new one.",
+    302: "This session key has expired or is no longer valid. Please create a new one.",
+    309: "This transaction exceeds the spending limit of the session key.",
+    310: "This transaction exceeds the spending limit of the session key.",
+};
+
 export const getAuthErrorMessage = (error: unknown): ErrorDetails => {
+    // Typed errors sent by the server, e.g. the `Error` outcome of a wallet event
+    if (typeof error === "object" && error !== null && "code" in error && typeof error.code === "number") {
+        const userMessage = contractErrorMessages[error.code];
+        if (userMessage) {
+            return {
+                userMessage,
+                technicalMessage: "message" in error ? String(error.message) : undefined,
+            };
+        }
+    }
+
     const errorString = error instanceof Error ? error.message : String(error);
     
     // Account fetching errors
@@ -1,49 +1,78 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use axum::{
     extract::{Json, State},
     http::Method,
     response::IntoResponse,
-    routing::get,
+    routing::{get, post},
     Router,
 };
-use client_sdk::rest_client::NodeApiHttpClient;
+use client_sdk::{
+    contract_indexer::utoipa_axum::{router::OpenApiRouter, routes},
+    rest_client::NodeApiHttpClient,
+};
 use hyle_modules::{
     bus::{BusClientSender, SharedMessageBus},
     module_bus_client, module_handle_messages,
     modules::{
-        contract_state_indexer::CSIBusEvent, websocket::WsTopicMessage, BuildApiContextInner,
-        Module,
+        contract_state_indexer::CSIBusEvent,
+        websocket::{WsInMessage, WsTopicMessage},
+        BuildApiContextInner, Module,
     },
 };
 
 use sdk::ContractName;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use tower_http::cors::{Any, CorsLayer};
-use wallet::client::indexer::WalletEvent;
+use tracing::error;
+use wallet::client::indexer::{WalletEvent, WalletNotification, WalletOutcome};
 
-use crate::history::HistoryEvent;
+use crate::{
+    history::{self, HistoryEvent, HistoryIndexers},
+    relay,
+    ws::{Subscriptions, WsCommand, WsCommandCtx, WsError, WsReply, CONNECTION_TTL},
+};
 
 pub struct AppModule {
     bus: AppModuleBusClient,
+    commands: WsCommandCtx,
 }
 
 pub struct AppModuleCtx {
     pub api: Arc<BuildApiContextInner>,
     pub node_client: Arc<NodeApiHttpClient>,
     pub wallet_cn: ContractName,
+    /// How long the relay endpoints wait for a transaction to settle
+    pub relay_timeout: Duration,
+    /// Base URL of the contract indexers' routes
+    pub indexer_url: String,
+    pub histories: HistoryIndexers,
 }
 
 /// Messages received from WebSocket clients that will be processed by the system
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum AppWsInMessage {}
+pub type AppWsInMessage = WsCommand;
 
-/// Messages sent to WebSocket clients from the system
+/// Messages sent to WebSocket clients from the system.
+/// Events of an account are only sent to the clients that subscribed to it with one of its session keys,
+/// on the topic bound to their connection.
 #[derive(Debug, Clone, Serialize)]
 pub enum AppOutWsEvent {
     TxEvent(HistoryEvent),
-    WalletEvent { account: String, event: String }, // TODO: Type event for better error handling in frontend
+    WalletEvent {
+        account: String,
+        event: WalletOutcome,
+    },
+    /// Security changes of an account, e.g. sessions opened with a rotated password should log in again
+    WalletNotification(WalletNotification),
+    /// Reply to the command `request_id` of the client
+    Response {
+        request_id: u64,
+        result: Result<WsReply, WsError>,
+    },
 }
 
 module_bus_client! {
@@ -52,6 +81,7 @@ pub struct AppModuleBusClient {
     sender(WsTopicMessage<AppOutWsEvent>),
     receiver(CSIBusEvent<Vec<HistoryEvent>>),
     receiver(CSIBusEvent<WalletEvent>),
+    receiver(WsInMessage<AppWsInMessage>),
 }
 }
 
@@ -61,6 +91,10 @@ impl Module for AppModule {
     async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
         let state = RouterCtx {
             wallet_cn: ctx.wallet_cn.clone(),
+            node_client: ctx.node_client.clone(),
+            bus: Arc::new(bus.new_handle()),
+            relay_timeout: ctx.relay_timeout,
+            histories: ctx.histories.clone(),
         };
 
         // Create a CORS middleware
@@ -72,38 +106,77 @@ impl Module for AppModule {
         let api = Router::new()
             .route("/_health", get(health))
             .route("/api/config", get(get_config))
-            .with_state(state)
-            .layer(cors); // Apply the CORS middleware
+            .route("/api/send", post(relay::send))
+            .route("/api/transfer", post(relay::transfer))
+            .with_state(state.clone());
+
+        // History across all the indexed tokens
+        let (history_router, history_api) = OpenApiRouter::default()
+            .routes(routes!(history::get_merged_history))
+            .split_for_parts();
+        let history_router = history_router.with_state(state);
+        let api = api.merge(history_router).layer(cors); // Apply the CORS middleware
 
         if let Ok(mut guard) = ctx.api.router.lock() {
             if let Some(router) = guard.take() {
                 guard.replace(router.merge(api));
             }
         }
+        if let Ok(mut openapi) = ctx.api.openapi.lock() {
+            openapi.merge(history_api);
+        }
+        let commands = WsCommandCtx {
+            bus: Arc::new(bus.new_handle()),
+            node_client: ctx.node_client.clone(),
+            http: reqwest::Client::new(),
+            indexer_url: ctx.indexer_url.clone(),
+            wallet_cn: ctx.wallet_cn.clone(),
+            relay_timeout: ctx.relay_timeout,
+            histories: ctx.histories.clone(),
+            subscriptions: Arc::new(Mutex::new(Subscriptions::default())),
+        };
         let bus = AppModuleBusClient::new_from_bus(bus.new_handle()).await;
 
-        Ok(AppModule { bus })
+        Ok(AppModule { bus, commands })
     }
 
     async fn run(&mut self) -> Result<()> {
+        let mut prune = tokio::time::interval(CONNECTION_TTL / 3);
         module_handle_messages! {
             on_bus self.bus,
             listen <CSIBusEvent<Vec<HistoryEvent>>> event => {
                 for msg in event.event {
-                    self.bus.send(WsTopicMessage::new(
-                        msg.account.0.clone(),
-                        AppOutWsEvent::TxEvent(msg),
-                    ))?;
+                    self.publish(&msg.account.0.clone(), AppOutWsEvent::TxEvent(msg))?;
                 }
             }
             listen<CSIBusEvent<WalletEvent>> event => {
-                self.bus.send(WsTopicMessage::new(
-                    event.event.account.0.clone(),
+                if let Some(notification) = event.event.notification {
+                    let account = notification.account().0.clone();
+                    self.publish(&account, AppOutWsEvent::WalletNotification(notification))?;
+                }
+                self.publish(
+                    &event.event.account.0,
                     AppOutWsEvent::WalletEvent {
                         account: event.event.account.0.clone(),
-                        event:event.event.program_outputs
+                        event: event.event.outcome,
                     },
-                ))?;
+                )?;
+            }
+            listen<WsInMessage<AppWsInMessage>> msg => {
+                // Answered by a task not to block the bus
+                let commands = self.commands.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = commands.answer(msg.addr, msg.message).await {
+                        error!("Error answering WebSocket command: {:?}", e);
+                    }
+                });
+            }
+            _ = prune.tick() => {
+                self.commands
+                    .subscriptions
+                    .lock()
+                    .map_err(|_| anyhow::anyhow!("Subscriptions lock poisoned"))?
+                    .prune(Instant::now());
             }
         };
 
@@ -111,9 +184,30 @@ impl Module for AppModule {
     }
 }
 
+impl AppModule {
+    /// Sends `event` to the clients subscribed to the events of `identity`
+    fn publish(&mut self, identity: &str, event: AppOutWsEvent) -> Result<()> {
+        let subscribers = self
+            .commands
+            .subscriptions
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Subscriptions lock poisoned"))?
+            .subscribers(identity);
+        for client_id in subscribers {
+            self.bus
+                .send(WsTopicMessage::new(client_id, event.clone()))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
-struct RouterCtx {
+pub struct RouterCtx {
     pub wallet_cn: ContractName,
+    pub node_client: Arc<NodeApiHttpClient>,
+    pub bus: Arc<SharedMessageBus>,
+    pub relay_timeout: Duration,
+    pub histories: HistoryIndexers,
 }
 
 async fn health() -> impl IntoResponse {
@@ -129,65 +223,8 @@ struct ConfigResponse {
 //     Routes
 // --------------------------------------------------------
 
-// async fn increment(
-//     State(ctx): State<RouterCtx>,
-//     headers: HeaderMap,
-// ) -> Result<impl IntoResponse, AppError> {
-//     let auth = AuthHeaders::from_headers(&headers)?;
-//     send(ctx.clone(), auth).await
-// }
-
 async fn get_config(State(ctx): State<RouterCtx>) -> impl IntoResponse {
     Json(ConfigResponse {
         contract_name: ctx.wallet_cn.0,
     })
 }
-
-// async fn send(ctx: RouterCtx, auth: AuthHeaders) -> Result<impl IntoResponse, AppError> {
-//     let _header_session_key = auth.session_key.clone();
-//     let _header_signature = auth.signature.clone();
-//     let identity = auth.user.clone();
-//
-//     let action_wallet = WalletAction::Increment;
-//
-//     let blobs = vec![action_wallet.as_blob(ctx.wallet_cn.clone())];
-//
-//     let res = ctx
-//         .client
-//         .send_tx_blob(&BlobTransaction::new(identity.clone(), blobs))
-//         .await;
-//
-//     if let Err(ref e) = res {
-//         let root_cause = e.root_cause().to_string();
-//         return Err(AppError(
-//             StatusCode::BAD_REQUEST,
-//             anyhow::anyhow!("{}", root_cause),
-//         ));
-//     }
-//
-//     let tx_hash = res.unwrap();
-//
-//     let mut bus = {
-//         let app = ctx.app.lock().await;
-//         AppModuleBusClient::new_from_bus(app.bus.new_handle()).await
-//     };
-//
-//     tokio::time::timeout(Duration::from_secs(5), async {
-//         loop {
-//             let a = bus.recv().await?;
-//             match a {
-//                 AppEvent::SequencedTx(sequenced_tx_hash) => {
-//                     if sequenced_tx_hash == tx_hash {
-//                         return Ok(Json(sequenced_tx_hash));
-//                     }
-//                 }
-//                 AppEvent::FailedTx(sequenced_tx_hash, error) => {
-//                     if sequenced_tx_hash == tx_hash {
-//                         return Err(AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!(error)));
-//                     }
-//                 }
-//             }
-//         }
-//     })
-//     .await?
-// }
@@ -24,10 +24,51 @@ pub struct Conf {
     pub wallet_buffer_blocks: u32,
     pub wallet_max_txs_per_proof: usize,
 
+    /// SMT token contracts whose transactions are indexed in the history
+    pub tokens: Vec<TokenConf>,
+
+    /// Backend proving the contracts, see [`ProverBackend`]
+    pub prover: ProverBackend,
+
+    /// How long the relay endpoints wait for a transaction to settle, in seconds
+    pub relay_timeout_secs: u64,
+
     /// Websocket configuration
     pub websocket: WebSocketConfig,
 }
 
+/// An SMT token contract indexed by the server
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TokenConf {
+    pub name: String,
+    /// Whether the server proves the token's transactions, with the `smt_*` settings
+    #[serde(default)]
+    pub prove: bool,
+}
+
+/// How the server proves the transactions of its contracts
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProverBackend {
+    /// Real risc0 proofs, computed locally
+    #[default]
+    Risc0,
+    /// Fake risc0 proofs, only accepted by a node running with RISC0_DEV_MODE
+    Risc0Dev,
+    /// No proving, the contracts are executed natively. For tests, against a node's `test` verifier.
+    Mock,
+}
+
+impl ProverBackend {
+    /// Verifier the contracts are registered with
+    pub fn verifier(&self) -> &'static str {
+        match self {
+            ProverBackend::Risc0 | ProverBackend::Risc0Dev => "risc0-1",
+            ProverBackend::Mock => "test",
+        }
+    }
+}
+
 impl Conf {
     pub fn new(config_files: Vec<String>) -> Result<Self, anyhow::Error> {
         let mut s = Config::builder().add_source(File::from_str(
@@ -51,3 +92,32 @@ impl Conf {
         Ok(conf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_lists_the_indexed_tokens() {
+        let file = std::env::temp_dir().join(format!("tokens-{}.toml", std::process::id()));
+        std::fs::write(
+            &file,
+            "[[tokens]]\nname = \"oranj\"\nprove = true\n\n[[tokens]]\nname = \"vitamin\"\n",
+        )
+        .expect("Failed to write config file");
+
+        let conf = Conf::new(vec![file.display().to_string()]);
+        std::fs::remove_file(&file).expect("Failed to remove config file");
+
+        let tokens: Vec<_> = conf
+            .expect("Invalid config")
+            .tokens
+            .into_iter()
+            .map(|token| (token.name, token.prove))
+            .collect();
+        assert_eq!(
+            tokens,
+            [("oranj".to_string(), true), ("vitamin".to_string(), false)]
+        );
+    }
+}
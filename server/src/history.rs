@@ -1,84 +1,401 @@
 use anyhow::anyhow;
 use anyhow::Context;
+use borsh::io::Read;
 use borsh::BorshDeserialize;
 use borsh::BorshSerialize;
 use hyle_smt_token::client::tx_executor_handler::SmtTokenProvableState;
 use hyle_smt_token::SmtTokenAction;
 use sdk::BlobIndex;
 use sdk::Calldata;
+use sdk::ContractName;
 use sdk::Hashed;
 use sdk::RegisterContractEffect;
 use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
 
 use client_sdk::contract_indexer::axum;
 use client_sdk::contract_indexer::utoipa;
 use client_sdk::contract_indexer::{
     axum::{
-        extract::{Path, State},
-        http::StatusCode,
+        body::Body,
+        extract::{Path, Query, State},
+        http::{header, StatusCode},
         response::IntoResponse,
         Json, Router,
     },
-    utoipa::{openapi::OpenApi, ToSchema},
+    utoipa::{openapi::OpenApi, IntoParams, ToSchema},
     utoipa_axum::{router::OpenApiRouter, routes},
-    AppError, ContractHandler, ContractHandlerStore,
+    AppError, ContractHandler, ContractHandlerStore, Store,
 };
 use client_sdk::transaction_builder::TxExecutorHandler;
+use futures::StreamExt;
 use sdk::utils::parse_calldata;
 use sdk::Identity;
 use sdk::TxHash;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Default, Serialize, ToSchema, BorshDeserialize, BorshSerialize)]
+use crate::app::RouterCtx;
+
+/// Prefix of the versioned history in the persisted state. Histories without it are the untagged
+/// history of the first indexer, see [`v0`]. The history is Borsh encoded, which encodes the variants
+/// of an enum by position: add new ones at the end.
+const HISTORY_MAGIC: [u8; 4] = *b"hist";
+
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, ToSchema, BorshDeserialize, BorshSerialize,
+)]
 pub struct TransactionDetails {
     id: String,
-    r#type: String,
-    status: String,
+    r#type: TransactionType,
+    status: TransactionStatus,
     amount: u128,
     address: Identity,
     timestamp: u128,
 }
 
-#[derive(Debug, Clone, Default, BorshDeserialize, BorshSerialize)]
-pub struct HyllarHistory {
-    oranj: SmtTokenProvableState,
+/// What the transaction did, from the point of view of the account whose history it is in.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    ToSchema,
+    BorshDeserialize,
+    BorshSerialize,
+)]
+pub enum TransactionType {
+    #[default]
+    Send,
+    Receive,
+    Approve,
+    /// Tokens of the account sent by a spender
+    SendTransferFrom,
+    /// Tokens received from an owner, sent by a spender
+    ReceiveTransferFrom,
+}
+
+/// Where the transaction is in its settlement. It only moves forward, from `Sequenced` to a
+/// final status.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    ToSchema,
+    BorshDeserialize,
+    BorshSerialize,
+)]
+pub enum TransactionStatus {
+    #[default]
+    Sequenced,
+    Success,
+    Failed,
+    TimedOut,
+}
+
+impl TransactionStatus {
+    pub fn is_final(self) -> bool {
+        self != TransactionStatus::Sequenced
+    }
+
+    /// Moves to `next` if the transition is allowed. Settled transactions never change status.
+    pub fn transition(&mut self, next: TransactionStatus) -> bool {
+        if self.is_final() || !next.is_final() {
+            return false;
+        }
+        *self = next;
+        true
+    }
+}
+
+/// Layout of the history of the first indexer, with free-form types and statuses
+mod v0 {
+    use borsh::BorshDeserialize;
+    use sdk::Identity;
+
+    use super::{TransactionStatus, TransactionType};
+
+    #[derive(BorshDeserialize)]
+    pub struct TransactionDetails {
+        pub id: String,
+        pub r#type: String,
+        pub status: String,
+        pub amount: u128,
+        pub address: Identity,
+        pub timestamp: u128,
+    }
+
+    impl TryFrom<TransactionDetails> for super::TransactionDetails {
+        type Error = String;
+
+        fn try_from(tx: TransactionDetails) -> Result<Self, Self::Error> {
+            let r#type = match tx.r#type.as_str() {
+                "Send" => TransactionType::Send,
+                "Receive" => TransactionType::Receive,
+                "Approve" => TransactionType::Approve,
+                "Send TransferFrom" => TransactionType::SendTransferFrom,
+                "Receive TransferFrom" => TransactionType::ReceiveTransferFrom,
+                other => return Err(format!("Unknown transaction type '{other}'")),
+            };
+            let status = match tx.status.as_str() {
+                "Sequenced" => TransactionStatus::Sequenced,
+                "Success" => TransactionStatus::Success,
+                "Failed" => TransactionStatus::Failed,
+                "Timed Out" => TransactionStatus::TimedOut,
+                other => return Err(format!("Unknown transaction status '{other}'")),
+            };
+            Ok(super::TransactionDetails {
+                id: tx.id,
+                r#type,
+                status,
+                amount: tx.amount,
+                address: tx.address,
+                timestamp: tx.timestamp,
+            })
+        }
+    }
+}
+
+/// The history of the accounts, as persisted after [`HISTORY_MAGIC`].
+#[derive(BorshDeserialize, BorshSerialize)]
+enum VersionedHistory {
+    /// Entries from the most recent
+    V1(BTreeMap<Identity, Vec<TransactionDetails>>),
+    /// Entries from the oldest
+    V2(BTreeMap<Identity, Vec<TransactionDetails>>),
+}
+
+/// A history entry, with the token contract it was indexed from
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TokenTransaction {
+    token: ContractName,
+    #[serde(flatten)]
+    tx: TransactionDetails,
+}
+
+/// History of the transactions of an SMT token contract, by account
+#[derive(Debug, Clone, Default)]
+pub struct TokenHistory {
+    token: SmtTokenProvableState,
+    /// Entries of each account from the oldest, new ones being pushed at the end
     history: BTreeMap<Identity, Vec<TransactionDetails>>,
+    /// Entries of the transactions not settled yet, by transaction hash, with their position in the
+    /// history of the account. Not persisted, see [`Self::reindex`].
+    pending: BTreeMap<String, Vec<(Identity, usize)>>,
+}
+
+impl BorshSerialize for TokenHistory {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        self.token.serialize(writer)?;
+        writer.write_all(&HISTORY_MAGIC)?;
+        // Encoded as `VersionedHistory::V2`, without cloning the history
+        1u8.serialize(writer)?;
+        self.history.serialize(writer)
+    }
+}
+
+/// Also reads the history persisted by older versions, migrating it to the latest one
+impl BorshDeserialize for TokenHistory {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        let token = SmtTokenProvableState::deserialize_reader(reader)?;
+        let mut prefix = [0; HISTORY_MAGIC.len()];
+        reader.read_exact(&mut prefix)?;
+        let mut history = if prefix == HISTORY_MAGIC {
+            match VersionedHistory::deserialize_reader(reader)? {
+                VersionedHistory::V1(history) => from_the_oldest(history),
+                VersionedHistory::V2(history) => history,
+            }
+        } else {
+            // The first history starts with its number of accounts, which never matches the magic
+            let history = BTreeMap::<Identity, Vec<v0::TransactionDetails>>::deserialize_reader(
+                &mut prefix.as_slice().chain(reader),
+            )?;
+            history
+                .into_iter()
+                .map(|(account, txs)| {
+                    let txs = txs
+                        .into_iter()
+                        .map(TryInto::try_into)
+                        .collect::<Result<_, _>>()?;
+                    Ok((account, txs))
+                })
+                .collect::<Result<_, String>>()
+                .map(from_the_oldest)
+                .map_err(borsh::io::Error::other)?
+        };
+        let mut state = TokenHistory {
+            token,
+            history,
+            pending: BTreeMap::new(),
+        };
+        state.reindex();
+        Ok(state)
+    }
+}
+
+/// Reverses the histories persisted from the most recent entry
+fn from_the_oldest(
+    mut history: BTreeMap<Identity, Vec<TransactionDetails>>,
+) -> BTreeMap<Identity, Vec<TransactionDetails>> {
+    history.values_mut().for_each(|txs| txs.reverse());
+    history
 }
 
 #[derive(Debug, Clone, Default, Serialize)]
 pub struct HistoryEvent {
     pub account: Identity,
+    pub token: ContractName,
     pub tx: TransactionDetails,
 }
 
-impl HyllarHistory {
+/// The history indexers of the tokens, read through their routes for the merged history
+#[derive(Debug, Clone, Default)]
+pub struct HistoryIndexers {
+    pub http: reqwest::Client,
+    /// Base URL of the contract indexers' routes
+    pub indexer_url: String,
+    pub tokens: Vec<ContractName>,
+}
+
+impl HistoryIndexers {
+    /// The page of the history of `account` indexed for `token` at `cursor`, `None` if it has none
+    async fn page(
+        &self,
+        token: &ContractName,
+        account: &Identity,
+        cursor: Option<usize>,
+    ) -> anyhow::Result<Option<HistoryResponse>> {
+        let mut query = vec![("limit", MAX_PAGE_SIZE)];
+        query.extend(cursor.map(|cursor| ("cursor", cursor)));
+        let response = self
+            .http
+            .get(format!(
+                "{}/{}/history/{}",
+                self.indexer_url, token.0, account.0
+            ))
+            .query(&query)
+            .send()
+            .await
+            .with_context(|| format!("Failed to read the history of {}", token.0))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let page = response
+            .error_for_status()
+            .with_context(|| format!("Failed to read the history of {}", token.0))?
+            .json()
+            .await?;
+        Ok(Some(page))
+    }
+}
+
+impl TokenHistory {
+    #[allow(clippy::too_many_arguments, reason = "Fields of the entry")]
     pub fn add_to_history(
         &mut self,
+        token: &ContractName,
         identity: Identity,
         address: Identity,
-        action: &str,
+        r#type: TransactionType,
         amount: u128,
         tx_hash: TxHash,
         timestamp: u128,
     ) -> HistoryEvent {
         let transaction = TransactionDetails {
             id: tx_hash.0,
-            r#type: action.to_string(),
+            r#type,
             amount,
             address,
             timestamp,
-            status: "Sequenced".to_string(),
+            status: TransactionStatus::Sequenced,
         };
-        self.history
-            .entry(identity.clone())
+        let history = self.history.entry(identity.clone()).or_default();
+        history.push(transaction.clone());
+        self.pending
+            .entry(transaction.id.clone())
             .or_default()
-            .insert(0, transaction.clone());
+            .push((identity.clone(), history.len() - 1));
         HistoryEvent {
             account: identity,
+            token: token.clone(),
             tx: transaction,
         }
     }
 
+    /// Indexes the entries not settled yet
+    fn reindex(&mut self) {
+        self.pending.clear();
+        for (account, history) in &self.history {
+            for (position, tx) in history.iter().enumerate() {
+                if !tx.status.is_final() {
+                    self.pending
+                        .entry(tx.id.clone())
+                        .or_default()
+                        .push((account.clone(), position));
+                }
+            }
+        }
+    }
+
+    /// Moves the entries of `tx_hash` to the final `status`, returning them with their account
+    fn settle(
+        &mut self,
+        tx_hash: &TxHash,
+        status: TransactionStatus,
+    ) -> Vec<(Identity, TransactionDetails)> {
+        let mut settled = vec![];
+        for (account, position) in self.pending.remove(&tx_hash.0).unwrap_or_default() {
+            let Some(history) = self.history.get_mut(&account) else {
+                continue;
+            };
+            if let Some(tx) = history.get_mut(position) {
+                if tx.status.transition(status) {
+                    settled.push((account, tx.clone()));
+                }
+            }
+        }
+        settled
+    }
+
+    /// Settles `tx`, notifying every account whose history contains it
+    fn settled_events(
+        &mut self,
+        tx: &sdk::BlobTransaction,
+        index: BlobIndex,
+        status: TransactionStatus,
+    ) -> Option<Vec<HistoryEvent>> {
+        let token = Self::token(tx, index);
+        let events: Vec<_> = self
+            .settle(&tx.hashed(), status)
+            .into_iter()
+            .map(|(account, tx)| HistoryEvent {
+                account,
+                token: token.clone(),
+                tx,
+            })
+            .collect();
+        (!events.is_empty()).then_some(events)
+    }
+
+    /// The token contract of the blob at `index`
+    fn token(tx: &sdk::BlobTransaction, index: BlobIndex) -> ContractName {
+        tx.blobs
+            .get(index.0)
+            .map(|blob| blob.contract_name.clone())
+            .unwrap_or_default()
+    }
+
     fn get_action(tx: &sdk::BlobTransaction, index: BlobIndex) -> anyhow::Result<SmtTokenAction> {
         let calldata = Calldata {
             identity: tx.identity.clone(),
@@ -95,13 +412,13 @@ impl HyllarHistory {
     }
 }
 
-impl TxExecutorHandler for HyllarHistory {
+impl TxExecutorHandler for TokenHistory {
     fn handle(&mut self, calldata: &sdk::Calldata) -> anyhow::Result<sdk::HyleOutput> {
-        self.oranj.handle(calldata)
+        self.token.handle(calldata)
     }
 
     fn build_commitment_metadata(&self, blob: &sdk::Blob) -> anyhow::Result<Vec<u8>> {
-        self.oranj.build_commitment_metadata(blob)
+        self.token.build_commitment_metadata(blob)
     }
 
     fn construct_state(
@@ -112,10 +429,14 @@ impl TxExecutorHandler for HyllarHistory {
     }
 }
 
-impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
-    async fn api(store: ContractHandlerStore<HyllarHistory>) -> (Router<()>, OpenApi) {
+impl ContractHandler<Vec<HistoryEvent>> for TokenHistory {
+    async fn api(store: ContractHandlerStore<TokenHistory>) -> (Router<()>, OpenApi) {
+        // Nothing to hand over when not built by `build_indexer`
+        let _ = BUILT_STORE.try_with(|built| built.replace(Some(store.clone())));
+
         let (router, api) = OpenApiRouter::default()
             .routes(routes!(get_history))
+            .routes(routes!(export_history))
             .split_for_parts();
 
         (router.with_state(store), api)
@@ -124,70 +445,28 @@ impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
     fn handle_transaction_success(
         &mut self,
         tx: &sdk::BlobTransaction,
-        _index: sdk::BlobIndex,
+        index: sdk::BlobIndex,
         _tx_context: sdk::TxContext,
     ) -> anyhow::Result<Option<Vec<HistoryEvent>>> {
-        let mut events = vec![];
-        self.history.iter_mut().for_each(|(account, history)| {
-            for t in history.iter_mut().filter(|t| t.id == tx.hashed().0) {
-                t.status = "Success".to_string();
-                events.push(HistoryEvent {
-                    account: account.clone(),
-                    tx: t.clone(),
-                });
-            }
-        });
-        if !events.is_empty() {
-            Ok(Some(events))
-        } else {
-            Ok(None)
-        }
+        Ok(self.settled_events(tx, index, TransactionStatus::Success))
     }
 
     fn handle_transaction_failed(
         &mut self,
         tx: &sdk::BlobTransaction,
-        _index: sdk::BlobIndex,
+        index: sdk::BlobIndex,
         _tx_context: sdk::TxContext,
     ) -> anyhow::Result<Option<Vec<HistoryEvent>>> {
-        let mut events = vec![];
-        self.history.values_mut().for_each(|history| {
-            for t in history.iter_mut().filter(|t| t.id == tx.hashed().0) {
-                t.status = "Failed".to_string();
-                events.push(HistoryEvent {
-                    account: tx.identity.clone(),
-                    tx: t.clone(),
-                });
-            }
-        });
-        if !events.is_empty() {
-            Ok(Some(events))
-        } else {
-            Ok(None)
-        }
+        Ok(self.settled_events(tx, index, TransactionStatus::Failed))
     }
 
     fn handle_transaction_timeout(
         &mut self,
         tx: &sdk::BlobTransaction,
-        _index: sdk::BlobIndex,
+        index: sdk::BlobIndex,
         _tx_context: sdk::TxContext,
     ) -> anyhow::Result<Option<Vec<HistoryEvent>>> {
-        let mut events = vec![];
-        self.history.values_mut().for_each(|history| {
-            for t in history.iter_mut().filter(|t| t.id == tx.hashed().0) {
-                t.status = "Timed Out".to_string();
-                events.push(HistoryEvent {
-                    account: tx.identity.clone(),
-                    tx: t.clone(),
-                });
-            }
-        });
-        if !events.is_empty() {
-            Ok(Some(events))
-        } else {
-            Ok(None)
-        }
+        Ok(self.settled_events(tx, index, TransactionStatus::TimedOut))
     }
 
     fn handle_transaction_sequenced(
@@ -199,6 +478,7 @@ impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
         let action = Self::get_action(tx, index)
             .with_context(|| format!("Failed to get action for transaction: {:?}", tx))?;
         let timestamp = tx_context.timestamp.0;
+        let token = Self::token(tx, index);
         let mut events = vec![];
 
         match action {
@@ -209,18 +489,20 @@ impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
             } => {
                 // Update history for the sender
                 events.push(self.add_to_history(
+                    &token,
                     sender.clone(),
                     recipient.clone(),
-                    "Send",
+                    TransactionType::Send,
                     amount,
                     tx.hashed(),
                     timestamp,
                 ));
                 // Update history for the receiver
                 events.push(self.add_to_history(
+                    &token,
                     recipient,
                     sender,
-                    "Receive",
+                    TransactionType::Receive,
                     amount,
                     tx.hashed(),
                     timestamp,
@@ -232,9 +514,10 @@ impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
                 owner,
             } => {
                 events.push(self.add_to_history(
+                    &token,
                     owner,
                     spender,
-                    "Approve",
+                    TransactionType::Approve,
                     amount,
                     tx.hashed(),
                     timestamp,
@@ -247,17 +530,19 @@ impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
                 spender: _,
             } => {
                 events.push(self.add_to_history(
+                    &token,
                     recipient.clone(),
                     owner.clone(),
-                    "Receive TransferFrom",
+                    TransactionType::ReceiveTransferFrom,
                     amount,
                     tx.hashed(),
                     timestamp,
                 ));
                 events.push(self.add_to_history(
+                    &token,
                     owner,
                     recipient,
-                    "Send TransferFrom",
+                    TransactionType::SendTransferFrom,
                     amount,
                     tx.hashed(),
                     timestamp,
@@ -272,28 +557,202 @@ impl ContractHandler<Vec<HistoryEvent>> for HyllarHistory {
     }
 }
 
-#[derive(Serialize, ToSchema)]
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Filters of the history, and the page to return. Entries are sorted from the most recent.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// `next_cursor` of the previous page
+    cursor: Option<usize>,
+    /// Number of entries of the page, 50 by default, at most 500
+    limit: Option<usize>,
+    /// Only the transactions sequenced at or after this timestamp, in ms
+    from: Option<u64>,
+    /// Only the transactions sequenced before this timestamp, in ms
+    to: Option<u64>,
+    r#type: Option<TransactionType>,
+    status: Option<TransactionStatus>,
+    /// Only the transactions with this identity
+    counterparty: Option<Identity>,
+}
+
+impl HistoryQuery {
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn matches(&self, tx: &TransactionDetails) -> bool {
+        self.from
+            .is_none_or(|from| tx.timestamp >= u128::from(from))
+            && self.to.is_none_or(|to| tx.timestamp < u128::from(to))
+            && self.r#type.is_none_or(|t| t == tx.r#type)
+            && self.status.is_none_or(|s| s == tx.status)
+            && self.counterparty.as_ref().is_none_or(|c| *c == tx.address)
+    }
+
+    /// The page of the entries matching the filters in `history`, from the most recent.
+    /// Cursors count the entries from the oldest one, so they stay valid as new ones are added.
+    fn page(&self, history: &[TransactionDetails]) -> Page {
+        let (page, next_cursor) = self.entries(history);
+        Page {
+            history: page,
+            total: history.iter().filter(|tx| self.matches(tx)).count(),
+            next_cursor,
+        }
+    }
+
+    /// The entries of the page and the cursor of the next one, without counting the matching entries
+    fn entries(&self, history: &[TransactionDetails]) -> (Vec<TransactionDetails>, Option<usize>) {
+        let limit = self.limit();
+        let end = self
+            .cursor
+            .map_or(history.len(), |cursor| cursor.min(history.len()));
+
+        let mut matching = history[..end]
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, tx)| self.matches(tx));
+        let page = matching
+            .by_ref()
+            .take(limit)
+            .map(|(_, tx)| tx.clone())
+            .collect();
+        let next_cursor = matching.next().map(|(index, _)| index + 1);
+        (page, next_cursor)
+    }
+}
+
+struct Page {
+    history: Vec<TransactionDetails>,
+    total: usize,
+    next_cursor: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct HistoryResponse {
     account: String,
     history: Vec<TransactionDetails>,
+    /// Number of entries matching the filters, in all pages
+    total: usize,
+    /// Cursor of the next page, if any
+    next_cursor: Option<usize>,
+}
+
+/// History across all the indexed tokens
+#[derive(Serialize, ToSchema)]
+pub struct MergedHistoryResponse {
+    account: String,
+    history: Vec<TokenTransaction>,
+    /// Number of entries matching the filters, in all pages
+    total: usize,
+    /// Cursor of the next page, if any
+    #[schema(value_type = Option<String>)]
+    next_cursor: Option<MergedCursor>,
+}
+
+/// Filters of the merged history, and the page to return, see [`HistoryQuery`]
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MergedHistoryQuery {
+    /// `next_cursor` of the previous page
+    #[param(value_type = Option<String>)]
+    cursor: Option<MergedCursor>,
+    /// Number of entries of the page, 50 by default, at most 500
+    limit: Option<usize>,
+    /// Only the transactions sequenced at or after this timestamp, in ms
+    from: Option<u64>,
+    /// Only the transactions sequenced before this timestamp, in ms
+    to: Option<u64>,
+    r#type: Option<TransactionType>,
+    status: Option<TransactionStatus>,
+    /// Only the transactions with this identity
+    counterparty: Option<Identity>,
+}
+
+impl MergedHistoryQuery {
+    fn filters(&self) -> HistoryQuery {
+        HistoryQuery {
+            cursor: None,
+            limit: self.limit,
+            from: self.from,
+            to: self.to,
+            r#type: self.r#type,
+            status: self.status,
+            counterparty: self.counterparty.clone(),
+        }
+    }
 }
+
+/// Position of an entry in the merged history, sorted by timestamp, then token, then index in the
+/// history of the token counted from the oldest entry. It does not change as entries are added to any
+/// of the tokens, whatever the order their indexers catch up in.
+/// Written `{timestamp}.{index}.{token}` in the queries.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct MergedCursor {
+    timestamp: u128,
+    token: ContractName,
+    index: usize,
+}
+
+impl fmt::Display for MergedCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.timestamp, self.index, self.token.0)
+    }
+}
+
+impl FromStr for MergedCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '.');
+        let mut next = || parts.next().ok_or_else(|| anyhow!("Invalid cursor '{s}'"));
+        Ok(MergedCursor {
+            timestamp: next()?.parse()?,
+            index: next()?.parse()?,
+            token: ContractName(next()?.to_string()),
+        })
+    }
+}
+
+impl From<MergedCursor> for String {
+    fn from(cursor: MergedCursor) -> Self {
+        cursor.to_string()
+    }
+}
+
+impl TryFrom<String> for MergedCursor {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 #[utoipa::path(
     get,
     path = "/history/{account}",
     params(
-        ("account" = String, Path, description = "Account")
+        ("account" = String, Path, description = "Account"),
+        HistoryQuery
     ),
     tag = "Contract",
     responses(
-        (status = OK, description = "Get balance of account", body = HistoryResponse)
+        (status = OK, description = "Get a page of the transaction history of account", body = HistoryResponse)
     )
 )]
 pub async fn get_history(
     Path(account): Path<Identity>,
-    State(state): State<ContractHandlerStore<HyllarHistory>>,
+    Query(query): Query<HistoryQuery>,
+    State(state): State<ContractHandlerStore<TokenHistory>>,
 ) -> Result<impl IntoResponse, AppError> {
     let store = state.read().await;
-    let state = store.state.clone().ok_or(AppError(
+    let state = store.state.as_ref().ok_or(AppError(
         StatusCode::NOT_FOUND,
         anyhow!("Contract '{}' not found", store.contract_name),
     ))?;
@@ -301,10 +760,14 @@ pub async fn get_history(
     state
         .history
         .get(&account)
-        .cloned()
-        .map(|history| HistoryResponse {
-            account: account.0.clone(),
-            history,
+        .map(|history| {
+            let page = query.page(history);
+            HistoryResponse {
+                account: account.0.clone(),
+                history: page.history,
+                total: page.total,
+                next_cursor: page.next_cursor,
+            }
         })
         .map(Json)
         .ok_or_else(|| {
@@ -314,3 +777,894 @@ pub async fn get_history(
             )
         })
 }
+
+#[utoipa::path(
+    get,
+    path = "/history/{account}",
+    params(
+        ("account" = String, Path, description = "Account"),
+        MergedHistoryQuery
+    ),
+    tag = "History",
+    responses(
+        (status = OK, description = "Get a page of the transaction history of account across all tokens", body = MergedHistoryResponse)
+    )
+)]
+pub async fn get_merged_history(
+    Path(account): Path<Identity>,
+    Query(query): Query<MergedHistoryQuery>,
+    State(ctx): State<RouterCtx>,
+) -> Result<Json<MergedHistoryResponse>, AppError> {
+    merged_history(&ctx.histories, account, &query)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// The history of `account` in all the indexed tokens, sorted from the most recent
+pub async fn merged_history(
+    histories: &HistoryIndexers,
+    account: Identity,
+    query: &MergedHistoryQuery,
+) -> anyhow::Result<MergedHistoryResponse> {
+    let filters = query.filters();
+    let mut history = vec![];
+    for token in &histories.tokens {
+        let mut cursor = None;
+        // All the entries of the token, the filters applying to the merged history
+        while let Some(page) = histories.page(token, &account, cursor).await? {
+            // Cursors count the entries from the oldest one, starting at 1
+            let start = cursor.unwrap_or(page.total);
+            history.extend(
+                page.history
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, tx)| filters.matches(tx))
+                    .map(|(i, tx)| {
+                        let position = MergedCursor {
+                            timestamp: tx.timestamp,
+                            token: token.clone(),
+                            index: start - i,
+                        };
+                        (position, tx)
+                    }),
+            );
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+    }
+    history.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let total = history.len();
+    let mut entries = history.into_iter().skip_while(|(position, _)| {
+        query
+            .cursor
+            .as_ref()
+            .is_some_and(|cursor| position > cursor)
+    });
+    let page = entries
+        .by_ref()
+        .take(filters.limit())
+        .map(|(position, tx)| TokenTransaction {
+            token: position.token,
+            tx,
+        })
+        .collect();
+    Ok(MergedHistoryResponse {
+        account: account.0,
+        history: page,
+        total,
+        next_cursor: entries.next().map(|(position, _)| position),
+    })
+}
+
+/// Entries read at once from the state while exporting
+const EXPORT_CHUNK_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/jsonl",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    fn header(self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Csv => Some("token,id,type,status,amount,counterparty,timestamp\n"),
+            ExportFormat::Jsonl => None,
+        }
+    }
+
+    /// The entry as a line of the export
+    fn line(self, token: &ContractName, tx: &TransactionDetails) -> String {
+        let timestamp = i64::try_from(tx.timestamp)
+            .ok()
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+            .unwrap_or_default();
+        match self {
+            ExportFormat::Csv => format!(
+                "{},{},{:?},{:?},{},{},{}\n",
+                csv_field(&token.0),
+                csv_field(&tx.id),
+                tx.r#type,
+                tx.status,
+                tx.amount,
+                csv_field(&tx.address.0),
+                timestamp
+            ),
+            ExportFormat::Jsonl => {
+                let entry = ExportEntry {
+                    token,
+                    id: &tx.id,
+                    r#type: tx.r#type,
+                    status: tx.status,
+                    amount: tx.amount,
+                    counterparty: &tx.address,
+                    timestamp,
+                };
+                let line = serde_json::to_string(&entry).expect("Failed to encode ExportEntry");
+                format!("{line}\n")
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportEntry<'a> {
+    token: &'a ContractName,
+    id: &'a str,
+    r#type: TransactionType,
+    status: TransactionStatus,
+    amount: u128,
+    counterparty: &'a Identity,
+    /// ISO 8601
+    timestamp: String,
+}
+
+/// Quotes the field if it contains a separator.
+/// A field that spreadsheets would read as a formula is prefixed with `'`, and quoted.
+fn csv_field(field: &str) -> String {
+    if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("\"'{}\"", field.replace('"', "\"\""))
+    } else if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `csv` by default
+    #[serde(default)]
+    format: ExportFormat,
+    /// Only the transactions sequenced at or after this timestamp, in ms
+    from: Option<u64>,
+    /// Only the transactions sequenced before this timestamp, in ms
+    to: Option<u64>,
+}
+
+impl ExportQuery {
+    pub fn new(format: ExportFormat, from: Option<u64>, to: Option<u64>) -> Self {
+        ExportQuery { format, from, to }
+    }
+
+    fn filters(&self) -> HistoryQuery {
+        HistoryQuery {
+            limit: Some(EXPORT_CHUNK_SIZE),
+            from: self.from,
+            to: self.to,
+            ..Default::default()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/history/{account}/export",
+    params(
+        ("account" = String, Path, description = "Account"),
+        ExportQuery
+    ),
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Stream the transaction history of account, from the most recent", content_type = "text/csv"),
+        (status = NOT_FOUND, description = "No history for account")
+    )
+)]
+pub async fn export_history(
+    Path(account): Path<Identity>,
+    Query(query): Query<ExportQuery>,
+    State(store): State<ContractHandlerStore<TokenHistory>>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = {
+        let store = store.read().await;
+        if !store
+            .state
+            .as_ref()
+            .is_some_and(|state| state.history.contains_key(&account))
+        {
+            return Err(AppError(
+                StatusCode::NOT_FOUND,
+                anyhow!("No history found for account '{}'", account),
+            ));
+        }
+        store.contract_name.clone()
+    };
+    let format = query.format;
+    let filename = format!("{}-{}.{}", account.0, token.0, format.extension());
+
+    // Pages are read one at a time, not to hold the state's lock during the download
+    let entries = futures::stream::unfold(Some(None), move |cursor| {
+        let (store, account, token) = (store.clone(), account.clone(), token.clone());
+        let filters = HistoryQuery {
+            cursor: cursor.flatten(),
+            ..query.filters()
+        };
+        async move {
+            cursor?;
+            let store = store.read().await;
+            let (entries, next_cursor) =
+                filters.entries(store.state.as_ref()?.history.get(&account)?);
+            let chunk: String = entries.iter().map(|tx| format.line(&token, tx)).collect();
+            Some((chunk, next_cursor.map(Some)))
+        }
+    });
+    let lines = futures::stream::iter(format.header().map(String::from))
+        .chain(entries)
+        .map(Ok::<_, Infallible>);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(lines),
+    ))
+}
+
+/// Writes the history of `account` persisted by the indexer of `token` in `data_directory`
+pub fn export_from_disk(
+    data_directory: &std::path::Path,
+    token: &ContractName,
+    account: &Identity,
+    query: &ExportQuery,
+    header: bool,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    // As saved by the contract state indexer
+    let file = data_directory.join(format!("state_indexer_{}.bin", token.0).replace(':', "_"));
+    let bytes =
+        std::fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let store: Store<TokenHistory> = borsh::from_slice(&bytes)
+        .with_context(|| format!("Failed to decode {}", file.display()))?;
+
+    if let Some(header) = query.format.header().filter(|_| header) {
+        writer.write_all(header.as_bytes())?;
+    }
+    let Some(history) = store
+        .state
+        .as_ref()
+        .and_then(|state| state.history.get(account))
+    else {
+        return Ok(());
+    };
+    let filters = query.filters();
+    for tx in history.iter().rev().filter(|tx| filters.matches(tx)) {
+        writer.write_all(query.format.line(token, tx).as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// `count` transfers, from the oldest, alternately sent to `alice` and received from `bob`
+    fn transfers(count: u128) -> Vec<TransactionDetails> {
+        (0..count)
+            .map(|i| TransactionDetails {
+                id: format!("tx{i}"),
+                r#type: if i % 2 == 0 {
+                    TransactionType::Send
+                } else {
+                    TransactionType::Receive
+                },
+                status: TransactionStatus::Success,
+                amount: i,
+                address: if i % 2 == 0 { "alice" } else { "bob" }.into(),
+                timestamp: i * 1000,
+            })
+            .collect()
+    }
+
+    fn ids(response: &Page) -> Vec<&str> {
+        response.history.iter().map(|tx| tx.id.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_stay_stable_as_transactions_are_added() {
+        let mut history = transfers(5);
+        let query = HistoryQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = query.page(&history);
+        assert_eq!(ids(&first), ["tx4", "tx3"]);
+        assert_eq!(first.total, 5);
+
+        // Added at the end of the history
+        history.push(transfers(6)[5].clone());
+
+        let query = HistoryQuery {
+            cursor: first.next_cursor,
+            ..query
+        };
+        let second = query.page(&history);
+        assert_eq!(ids(&second), ["tx2", "tx1"]);
+        assert_eq!(second.total, 6);
+
+        let query = HistoryQuery {
+            cursor: second.next_cursor,
+            ..query
+        };
+        let last = query.page(&history);
+        assert_eq!(ids(&last), ["tx0"]);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn filters_apply_before_paging() {
+        let history = transfers(10);
+        let query = HistoryQuery {
+            limit: Some(2),
+            r#type: Some(TransactionType::Send),
+            counterparty: Some("alice".into()),
+            from: Some(2000),
+            to: Some(8000),
+            ..Default::default()
+        };
+        let first = query.page(&history);
+        assert_eq!(ids(&first), ["tx6", "tx4"]);
+        assert_eq!(first.total, 3);
+
+        let query = HistoryQuery {
+            cursor: first.next_cursor,
+            ..query
+        };
+        let last = query.page(&history);
+        assert_eq!(ids(&last), ["tx2"]);
+        assert_eq!(last.next_cursor, None);
+
+        let query = HistoryQuery {
+            status: Some(TransactionStatus::Failed),
+            ..Default::default()
+        };
+        assert_eq!(query.page(&history).total, 0);
+    }
+
+    #[test]
+    fn settled_status_never_changes() {
+        let mut status = TransactionStatus::Sequenced;
+        assert!(!status.transition(TransactionStatus::Sequenced));
+        assert!(status.transition(TransactionStatus::Failed));
+        assert_eq!(status, TransactionStatus::Failed);
+
+        for next in [
+            TransactionStatus::Sequenced,
+            TransactionStatus::Success,
+            TransactionStatus::TimedOut,
+        ] {
+            assert!(!status.transition(next));
+            assert_eq!(status, TransactionStatus::Failed);
+        }
+    }
+
+    #[test]
+    fn legacy_history_is_migrated_on_load() {
+        let legacy = |r#type: &str, status: &str| {
+            (
+                "tx".to_string(),
+                r#type.to_string(),
+                status.to_string(),
+                10u128,
+                Identity::from("bob"),
+                1000u128,
+            )
+        };
+        let history = BTreeMap::from([(
+            Identity::from("alice"),
+            vec![
+                legacy("Receive TransferFrom", "Timed Out"),
+                legacy("Send", "Sequenced"),
+            ],
+        )]);
+        let mut bytes = borsh::to_vec(&SmtTokenProvableState::default()).unwrap();
+        bytes.extend(borsh::to_vec(&history).unwrap());
+        // Persisted along other fields by the indexer
+        bytes.extend(borsh::to_vec("oranj").unwrap());
+
+        let (state, name): (TokenHistory, String) = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(name, "oranj");
+        // Now from the oldest entry
+        let txs = &state.history[&Identity::from("alice")];
+        assert_eq!(txs[0].r#type, TransactionType::Send);
+        assert_eq!(txs[0].status, TransactionStatus::Sequenced);
+        assert_eq!(txs[1].r#type, TransactionType::ReceiveTransferFrom);
+        assert_eq!(txs[1].status, TransactionStatus::TimedOut);
+
+        // Saved in the latest version
+        let bytes = borsh::to_vec(&(&state, "oranj")).unwrap();
+        let (decoded, name): (TokenHistory, String) = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(name, "oranj");
+        assert_eq!(
+            decoded.history[&Identity::from("alice")][1].status,
+            TransactionStatus::TimedOut
+        );
+    }
+
+    #[test]
+    fn history_from_the_most_recent_is_reversed_on_load() {
+        let mut recent_first = BTreeMap::from([(Identity::from("alice"), transfers(3))]);
+        recent_first.values_mut().for_each(|txs| txs.reverse());
+        let mut bytes = borsh::to_vec(&SmtTokenProvableState::default()).unwrap();
+        bytes.extend(HISTORY_MAGIC);
+        bytes.extend(borsh::to_vec(&VersionedHistory::V1(recent_first)).unwrap());
+
+        let ids = |state: &TokenHistory| -> Vec<String> {
+            state.history[&Identity::from("alice")]
+                .iter()
+                .map(|tx| tx.id.clone())
+                .collect()
+        };
+        let state: TokenHistory = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(ids(&state), ["tx0", "tx1", "tx2"]);
+        // Saved from the oldest entry
+        let decoded: TokenHistory = borsh::from_slice(&borsh::to_vec(&state).unwrap()).unwrap();
+        assert_eq!(ids(&decoded), ["tx0", "tx1", "tx2"]);
+    }
+
+    fn add(state: &mut TokenHistory, account: &str, tx_hash: &str, timestamp: u128) {
+        state.add_to_history(
+            &"oranj".into(),
+            account.into(),
+            "bob".into(),
+            TransactionType::Send,
+            1,
+            TxHash(tx_hash.to_string()),
+            timestamp,
+        );
+    }
+
+    #[test]
+    fn settlement_finds_entries_followed_by_newer_ones() {
+        let mut state = TokenHistory::default();
+        add(&mut state, "alice", "tx0", 0);
+        add(&mut state, "carol", "tx0", 0);
+        add(&mut state, "alice", "tx1", 1);
+        add(&mut state, "alice", "tx2", 2);
+
+        let settled = state.settle(&TxHash("tx0".to_string()), TransactionStatus::Success);
+        let accounts: Vec<_> = settled
+            .iter()
+            .map(|(account, _)| account.0.as_str())
+            .collect();
+        assert_eq!(accounts, ["alice", "carol"]);
+        assert_eq!(state.history[&Identity::from("alice")][0].id, "tx0");
+        assert_eq!(
+            state.history[&Identity::from("alice")][0].status,
+            TransactionStatus::Success
+        );
+        // Settled once
+        assert!(state
+            .settle(&TxHash("tx0".to_string()), TransactionStatus::Failed)
+            .is_empty());
+
+        // The pending entries are indexed again when loading the state
+        let mut state: TokenHistory = borsh::from_slice(&borsh::to_vec(&state).unwrap()).unwrap();
+        add(&mut state, "alice", "tx3", 3);
+        let settled = state.settle(&TxHash("tx1".to_string()), TransactionStatus::TimedOut);
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].1.id, "tx1");
+        assert_eq!(settled[0].1.status, TransactionStatus::TimedOut);
+    }
+
+    /// State of the indexer of `token`, with a transaction of alice at each of `timestamps`
+    fn token_store(token: &str, timestamps: &[u128]) -> Store<TokenHistory> {
+        let mut state = TokenHistory::default();
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            add(&mut state, "alice", &format!("{token}{i}"), *timestamp);
+        }
+        let mut store = Store::default();
+        store.state = Some(state);
+        store.contract_name = token.into();
+        store
+    }
+
+    fn history_store(token: &str, timestamps: &[u128]) -> ContractHandlerStore<TokenHistory> {
+        Arc::new(tokio::sync::RwLock::new(token_store(token, timestamps)))
+    }
+
+    /// Serves the routes of the history indexers of the `stores`, as the contract state indexers nest them
+    async fn serve(stores: &[&ContractHandlerStore<TokenHistory>]) -> HistoryIndexers {
+        let mut app = Router::new();
+        let mut tokens = vec![];
+        for store in stores {
+            let token = store.read().await.contract_name.clone();
+            let (routes, _) = TokenHistory::api((*store).clone()).await;
+            app = app.nest(&format!("/{}", token.0), routes);
+            tokens.push(token);
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind indexer");
+        let indexer_url = format!(
+            "http://{}",
+            listener.local_addr().expect("No indexer address")
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        HistoryIndexers {
+            http: reqwest::Client::new(),
+            indexer_url,
+            tokens,
+        }
+    }
+
+    fn merged_ids(response: &MergedHistoryResponse) -> Vec<&str> {
+        response
+            .history
+            .iter()
+            .map(|entry| entry.tx.id.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn merged_pages_stay_stable_as_tokens_catch_up() {
+        let vitamin = history_store("vitamin", &[]);
+        let histories = serve(&[&history_store("oranj", &[1000, 3000, 3000]), &vitamin]).await;
+        let query = MergedHistoryQuery {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let first = merged_history(&histories, "alice".into(), &query)
+            .await
+            .unwrap();
+        assert_eq!(merged_ids(&first), ["oranj2"]);
+        assert_eq!(first.total, 3);
+        assert_eq!(
+            first.next_cursor,
+            Some("3000.2.oranj".parse().expect("Invalid cursor"))
+        );
+
+        // The indexer of vitamin catches up with a transaction of the same block
+        add(
+            vitamin.write().await.state.as_mut().unwrap(),
+            "alice",
+            "vitamin0",
+            3000,
+        );
+
+        let query = MergedHistoryQuery {
+            cursor: first.next_cursor,
+            ..query
+        };
+        let second = merged_history(&histories, "alice".into(), &query)
+            .await
+            .unwrap();
+        assert_eq!(merged_ids(&second), ["oranj1"]);
+        assert_eq!(second.total, 4);
+
+        let query = MergedHistoryQuery {
+            cursor: second.next_cursor,
+            ..query
+        };
+        let last = merged_history(&histories, "alice".into(), &query)
+            .await
+            .unwrap();
+        assert_eq!(merged_ids(&last), ["oranj0"]);
+        assert_eq!(last.next_cursor, None);
+
+        let query = MergedHistoryQuery {
+            from: Some(2000),
+            ..Default::default()
+        };
+        let recent = merged_history(&histories, "alice".into(), &query)
+            .await
+            .unwrap();
+        assert_eq!(merged_ids(&recent), ["vitamin0", "oranj2", "oranj1"]);
+        assert_eq!(recent.total, 3);
+    }
+
+    #[tokio::test]
+    async fn merged_history_reads_every_page_of_the_tokens() {
+        let timestamps: Vec<u128> = (0..2 * MAX_PAGE_SIZE as u128 + 1).collect();
+        let histories = serve(&[&history_store("oranj", &timestamps)]).await;
+        let query = MergedHistoryQuery {
+            to: Some(1),
+            ..Default::default()
+        };
+        let oldest = merged_history(&histories, "alice".into(), &query)
+            .await
+            .unwrap();
+        assert_eq!(merged_ids(&oldest), ["oranj0"]);
+        assert_eq!(oldest.total, 1);
+
+        let all = merged_history(&histories, "alice".into(), &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(all.total, timestamps.len());
+        assert_eq!(
+            all.next_cursor,
+            Some(
+                format!(
+                    "{}.{}.oranj",
+                    timestamps.len() - DEFAULT_PAGE_SIZE - 1,
+                    timestamps.len() - DEFAULT_PAGE_SIZE
+                )
+                .parse()
+                .expect("Invalid cursor")
+            )
+        );
+    }
+
+    fn token_tx(action: SmtTokenAction) -> sdk::BlobTransaction {
+        let blob = sdk::Blob {
+            contract_name: "oranj".into(),
+            data: sdk::BlobData(borsh::to_vec(&action).unwrap()),
+        };
+        sdk::BlobTransaction::new(Identity::from("sender@wallet"), vec![blob])
+    }
+
+    fn accounts(events: Option<Vec<HistoryEvent>>) -> Vec<String> {
+        let mut accounts: Vec<_> = events
+            .unwrap_or_default()
+            .into_iter()
+            .map(|event| event.account.0)
+            .collect();
+        accounts.sort();
+        accounts
+    }
+
+    #[test]
+    fn settlement_notifies_every_account_of_the_transaction() {
+        let actions = [
+            (
+                SmtTokenAction::Transfer {
+                    sender: "alice".into(),
+                    recipient: "bob".into(),
+                    amount: 10,
+                },
+                vec!["alice", "bob"],
+            ),
+            (
+                SmtTokenAction::Approve {
+                    owner: "alice".into(),
+                    spender: "bob".into(),
+                    amount: 10,
+                },
+                vec!["alice"],
+            ),
+            (
+                SmtTokenAction::TransferFrom {
+                    owner: "alice".into(),
+                    spender: "bob".into(),
+                    recipient: "carol".into(),
+                    amount: 10,
+                },
+                vec!["alice", "carol"],
+            ),
+        ];
+        let ctx = sdk::TxContext {
+            timestamp: sdk::hyle_model_utils::TimestampMs(1000),
+            ..Default::default()
+        };
+
+        for (action, expected) in actions {
+            for status in [
+                TransactionStatus::Success,
+                TransactionStatus::Failed,
+                TransactionStatus::TimedOut,
+            ] {
+                let mut state = TokenHistory::default();
+                let tx = token_tx(action.clone());
+                let sequenced = state
+                    .handle_transaction_sequenced(&tx, BlobIndex(0), ctx.clone())
+                    .unwrap();
+                assert_eq!(accounts(sequenced), expected, "{action:?}");
+
+                let settled = match status {
+                    TransactionStatus::Success => {
+                        state.handle_transaction_success(&tx, BlobIndex(0), ctx.clone())
+                    }
+                    TransactionStatus::Failed => {
+                        state.handle_transaction_failed(&tx, BlobIndex(0), ctx.clone())
+                    }
+                    _ => state.handle_transaction_timeout(&tx, BlobIndex(0), ctx.clone()),
+                }
+                .unwrap();
+                for event in settled.iter().flatten() {
+                    assert_eq!(event.token, ContractName::from("oranj"));
+                    assert_eq!(event.tx.status, status);
+                }
+                assert_eq!(accounts(settled), expected, "{action:?} {status:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn export_lines() {
+        let tx = TransactionDetails {
+            id: "tx0".to_string(),
+            r#type: TransactionType::ReceiveTransferFrom,
+            status: TransactionStatus::TimedOut,
+            amount: u128::MAX,
+            address: "bob,\"the\" builder".into(),
+            timestamp: 1_700_000_000_123,
+        };
+        let token = ContractName::from("oranj");
+
+        assert_eq!(
+            ExportFormat::Csv.line(&token, &tx),
+            format!(
+                "oranj,tx0,ReceiveTransferFrom,TimedOut,{},\"bob,\"\"the\"\" builder\",2023-11-14T22:13:20.123Z\n",
+                u128::MAX
+            )
+        );
+        let line: serde_json::Value =
+            serde_json::from_str(&ExportFormat::Jsonl.line(&token, &tx)).unwrap();
+        assert_eq!(line["token"], "oranj");
+        assert_eq!(line["type"], "ReceiveTransferFrom");
+        assert_eq!(line["status"], "TimedOut");
+        assert_eq!(line["counterparty"], "bob,\"the\" builder");
+        assert_eq!(line["timestamp"], "2023-11-14T22:13:20.123Z");
+    }
+
+    #[test]
+    fn csv_export_escapes_formulas() {
+        let tx = TransactionDetails {
+            id: "tx0".to_string(),
+            r#type: TransactionType::ReceiveTransferFrom,
+            status: TransactionStatus::Success,
+            amount: 1,
+            address: "=HYPERLINK(\"http://evil.example\",\"refund\")".into(),
+            timestamp: 1_700_000_000_123,
+        };
+        assert_eq!(
+            ExportFormat::Csv.line(&ContractName::from("oranj"), &tx),
+            "oranj,tx0,ReceiveTransferFrom,Success,1,\"'=HYPERLINK(\"\"http://evil.example\"\",\"\"refund\"\")\",2023-11-14T22:13:20.123Z\n"
+        );
+
+        for field in ["+1", "-1", "@SUM(A1)", "\tcmd"] {
+            assert_eq!(csv_field(field), format!("\"'{field}\""));
+        }
+        assert_eq!(csv_field("bob@wallet"), "bob@wallet");
+    }
+
+    async fn exported(store: ContractHandlerStore<TokenHistory>, query: ExportQuery) -> String {
+        let response = export_history(Path("alice".into()), Query(query), State(store))
+            .await
+            .map_err(|AppError(_, error)| error)
+            .expect("Failed to export")
+            .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read the export");
+        String::from_utf8(body.to_vec()).expect("Invalid export")
+    }
+
+    /// The ids of the exported CSV lines, the header's included
+    fn csv_ids(csv: &str) -> Vec<&str> {
+        csv.lines()
+            .map(|line| line.split(',').nth(1).expect("Invalid CSV line"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn export_chains_the_chunks() {
+        let timestamps: Vec<u128> = (0..2 * EXPORT_CHUNK_SIZE as u128 + 200).collect();
+        let store = history_store("oranj", &timestamps);
+
+        let csv = exported(
+            store.clone(),
+            ExportQuery::new(ExportFormat::Csv, None, None),
+        )
+        .await;
+        let ids = csv_ids(&csv);
+        assert_eq!(ids.iter().filter(|id| **id == "id").count(), 1);
+        assert_eq!(ids[0], "id");
+        // Each entry once, from the most recent
+        let expected: Vec<_> = (0..timestamps.len())
+            .rev()
+            .map(|i| format!("oranj{i}"))
+            .collect();
+        assert_eq!(ids[1..], expected);
+
+        let jsonl = exported(
+            store,
+            ExportQuery::new(ExportFormat::Jsonl, Some(1_000), Some(1_100)),
+        )
+        .await;
+        assert_eq!(jsonl.lines().count(), 100);
+    }
+
+    #[test]
+    fn export_from_disk_reads_the_persisted_states() {
+        let directory = std::env::temp_dir().join(format!("history-export-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (token, timestamps) in [("oranj", [1_000u128, 2_000]), ("vitamin", [1_500, 2_500])] {
+            // As saved by the contract state indexer
+            std::fs::write(
+                directory.join(format!("state_indexer_{token}.bin")),
+                borsh::to_vec(&token_store(token, &timestamps)).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let query = ExportQuery::new(ExportFormat::Csv, Some(1_500), None);
+        let mut csv = vec![];
+        for (i, token) in ["oranj", "vitamin"].into_iter().enumerate() {
+            export_from_disk(
+                &directory,
+                &token.into(),
+                &"alice".into(),
+                &query,
+                i == 0,
+                &mut csv,
+            )
+            .unwrap();
+        }
+        let mut unknown = vec![];
+        export_from_disk(
+            &directory,
+            &"oranj".into(),
+            &"carol".into(),
+            &query,
+            true,
+            &mut unknown,
+        )
+        .unwrap();
+        let missing = export_from_disk(
+            &directory,
+            &"apple".into(),
+            &"alice".into(),
+            &query,
+            true,
+            &mut std::io::sink(),
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv_ids(&csv), ["id", "oranj1", "vitamin1", "vitamin0"]);
+        assert_eq!(
+            String::from_utf8(unknown).unwrap(),
+            ExportFormat::Csv.header().unwrap()
+        );
+        assert!(missing.is_err());
+    }
+}
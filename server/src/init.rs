@@ -1,4 +1,4 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use client_sdk::rest_client::{IndexerApiHttpClient, NodeApiClient, NodeApiHttpClient};
 use sdk::{api::APIRegisterContract, info, ContractName, ProgramId, StateCommitment};
 use std::{sync::Arc, time::Duration};
@@ -7,16 +7,40 @@ use tokio::time::timeout;
 pub struct ContractInit {
     pub name: ContractName,
     pub program_id: [u8; 32],
+    pub verifier: String,
     pub initial_state: StateCommitment,
+    /// Migrations the program runs on the state of the previous ones, shown on upgrade
+    pub migrations: Vec<String>,
+    /// State to register on upgrade, from the on-chain state commitment of the previous program
+    pub upgrade_state: Box<dyn Fn(&[u8]) -> Result<StateUpgrade> + Send + Sync>,
+}
+
+/// State of a contract registered with a new program
+pub struct StateUpgrade {
+    pub state_commitment: StateCommitment,
+    /// Given to the indexers and provers, to construct the state matching `state_commitment`
+    pub constructor_metadata: Vec<u8>,
+}
+
+/// What to do with a contract whose on-chain program differs from this build's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeMode {
+    /// Fail, the server must not run against another program
+    Refuse,
+    /// Only log the registrations and upgrades that would be made
+    DryRun,
+    /// Register this build's program, with the on-chain state upgraded if needed
+    Upgrade,
 }
 
 pub async fn init_node(
     node: Arc<NodeApiHttpClient>,
     indexer: Arc<IndexerApiHttpClient>,
     contracts: Vec<ContractInit>,
+    upgrade: UpgradeMode,
 ) -> Result<()> {
     for contract in contracts {
-        init_contract(&node, &indexer, contract).await?;
+        init_contract(&node, &indexer, contract, upgrade).await?;
     }
     Ok(())
 }
@@ -25,25 +49,67 @@ async fn init_contract(
     node: &NodeApiHttpClient,
     indexer: &IndexerApiHttpClient,
     contract: ContractInit,
+    upgrade: UpgradeMode,
 ) -> Result<()> {
     match indexer.get_indexer_contract(&contract.name).await {
         Ok(existing) => {
             let onchain_program_id = hex::encode(existing.program_id.as_slice());
             let program_id = hex::encode(contract.program_id);
-            if onchain_program_id != program_id {
+            if onchain_program_id == program_id {
+                info!("✅ {} contract is up to date", contract.name);
+                return Ok(());
+            }
+            if upgrade == UpgradeMode::Refuse {
                 bail!(
-                    "Invalid program_id for {}. On-chain version is {}, expected {}",
+                    "Invalid program_id for {}. On-chain version is {}, expected {}. Run with --upgrade-contract to upgrade it",
                     contract.name,
                     onchain_program_id,
                     program_id
                 );
             }
-            info!("✅ {} contract is up to date", contract.name);
+
+            info!("⬆️  Upgrade of {} contract", contract.name);
+            info!("   program_id: {} -> {}", onchain_program_id, program_id);
+            let state_upgrade = (contract.upgrade_state)(&existing.state_commitment)
+                .with_context(|| format!("upgrading the state of {}", contract.name))?;
+            if state_upgrade.state_commitment.0 == existing.state_commitment {
+                info!(
+                    "   state commitment kept: {}",
+                    hex::encode(existing.state_commitment.as_slice())
+                );
+            } else {
+                info!(
+                    "   state commitment: {} -> {}",
+                    hex::encode(existing.state_commitment.as_slice()),
+                    hex::encode(state_upgrade.state_commitment.0.as_slice())
+                );
+            }
+            for migration in &contract.migrations {
+                info!("   state migration: {}", migration);
+            }
+            if upgrade == UpgradeMode::DryRun {
+                return Ok(());
+            }
+
+            node.register_contract(APIRegisterContract {
+                verifier: contract.verifier.clone().into(),
+                program_id: ProgramId(contract.program_id.to_vec()),
+                state_commitment: state_upgrade.state_commitment,
+                contract_name: contract.name.clone(),
+                constructor_metadata: Some(state_upgrade.constructor_metadata),
+                ..Default::default()
+            })
+            .await?;
+            wait_contract_program_id(indexer, &contract.name, &contract.program_id).await?;
+            info!("✅ {} contract upgraded", contract.name);
+        }
+        Err(_) if upgrade == UpgradeMode::DryRun => {
+            info!("🚀 {} contract would be registered", contract.name);
         }
         Err(_) => {
             info!("🚀 Registering {} contract", contract.name);
             node.register_contract(APIRegisterContract {
-                verifier: "risc0-1".into(),
+                verifier: contract.verifier.clone().into(),
                 program_id: ProgramId(contract.program_id.to_vec()),
                 state_commitment: contract.initial_state,
                 contract_name: contract.name.clone(),
@@ -55,6 +121,25 @@ async fn init_contract(
     }
     Ok(())
 }
+
+async fn wait_contract_program_id(
+    indexer: &IndexerApiHttpClient,
+    contract: &ContractName,
+    program_id: &[u8],
+) -> anyhow::Result<()> {
+    timeout(Duration::from_secs(30), async {
+        loop {
+            match indexer.get_indexer_contract(contract).await {
+                Ok(existing) if existing.program_id.as_slice() == program_id => return Ok(()),
+                _ => {
+                    info!("⏰ Waiting for contract {contract} upgrade to be settled");
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+            }
+        }
+    })
+    .await?
+}
 async fn wait_contract_state(
     indexer: &IndexerApiHttpClient,
     contract: &ContractName,
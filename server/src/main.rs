@@ -1,13 +1,13 @@
 use anyhow::{Context, Result};
 use app::{AppModule, AppModuleCtx, AppOutWsEvent, AppWsInMessage};
 use axum::Router;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use client_sdk::{
-    helpers::risc0::Risc0Prover,
+    contract_indexer::Store,
     rest_client::{IndexerApiHttpClient, NodeApiHttpClient},
 };
-use conf::Conf;
-use history::{HistoryEvent, HyllarHistory};
+use conf::{Conf, ProverBackend};
+use history::{ExportFormat, ExportQuery, HistoryEvent, HistoryIndexers, TokenHistory};
 use hyle_modules::{
     bus::{metrics::BusMetrics, SharedMessageBus},
     modules::{
@@ -21,17 +21,30 @@ use hyle_modules::{
     utils::logger::setup_tracing,
 };
 
-use hyle_smt_token::client::tx_executor_handler::SmtTokenProvableState;
+use hyle_smt_token::{client::tx_executor_handler::SmtTokenProvableState, SmtToken};
 use prometheus::Registry;
-use sdk::{api::NodeInfo, info, ContractName, ZkContract};
-use std::sync::{Arc, Mutex};
+use sdk::{api::NodeInfo, info, ContractName, Identity, ZkContract};
+use std::{
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tracing::error;
-use wallet::{client::indexer::WalletEvent, Wallet};
+use wallet::{
+    client::{indexer::WalletEvent, tx_executor_handler::WalletProvableState},
+    migration::MIGRATIONS,
+    Wallet,
+};
 
 mod app;
 mod conf;
 mod history;
 mod init;
+mod prover;
+mod relay;
+mod ws;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -41,12 +54,99 @@ pub struct Args {
 
     #[arg(long, default_value = "wallet")]
     pub wallet_cn: String,
+
+    /// Upgrade the contracts whose on-chain program differs from this build's
+    #[arg(long)]
+    pub upgrade_contract: bool,
+
+    /// Only print the contract registrations and upgrades to make, then exit
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Export the transaction history of an account from the data directory, token by token,
+    /// without running the server
+    Export {
+        account: String,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+
+        /// Only the transactions sequenced at or after this timestamp, in ms
+        #[arg(long)]
+        from: Option<u64>,
+
+        /// Only the transactions sequenced before this timestamp, in ms
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Token contracts to export, all the configured ones by default
+        #[arg(long)]
+        token: Vec<String>,
+
+        /// File to write, the standard output by default
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let args = Args::parse();
-    let config = Conf::new(args.config_file).context("reading config file")?;
+    let config = Conf::new(args.config_file.clone()).context("reading config file")?;
+
+    if config.prover == ProverBackend::Risc0Dev {
+        // Read by the risc0 prover. Set before the runtime starts its threads, that could read the
+        // environment meanwhile.
+        std::env::set_var("RISC0_DEV_MODE", "1");
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("building the runtime")?
+        .block_on(run(args, config))
+}
+
+async fn run(args: Args, config: Conf) -> Result<()> {
+    if let Some(Command::Export {
+        account,
+        format,
+        from,
+        to,
+        token,
+        output,
+    }) = args.command
+    {
+        let tokens = if token.is_empty() {
+            config.tokens.iter().map(|t| t.name.clone()).collect()
+        } else {
+            token
+        };
+        let mut writer: Box<dyn Write> = match output {
+            Some(path) => Box::new(
+                File::create(&path).with_context(|| format!("creating {}", path.display()))?,
+            ),
+            None => Box::new(std::io::stdout().lock()),
+        };
+        let query = ExportQuery::new(format, from, to);
+        for (i, token) in tokens.into_iter().enumerate() {
+            history::export_from_disk(
+                &config.data_directory,
+                &ContractName(token),
+                &Identity(account.clone()),
+                &query,
+                i == 0,
+                &mut writer,
+            )?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
 
     setup_tracing(
         &config.log_format,
@@ -69,16 +169,36 @@ async fn main() -> Result<()> {
     let contracts = vec![init::ContractInit {
         name: wallet_cn.clone(),
         program_id: contracts::WALLET_ID,
+        verifier: config.prover.verifier().to_string(),
         initial_state: Wallet::default().commit(),
+        migrations: MIGRATIONS.iter().map(|m| m.to_string()).collect(),
+        upgrade_state: wallet_state_upgrade(config.data_directory.clone(), wallet_cn.clone()),
     }];
 
-    match init::init_node(node_client.clone(), indexer_client.clone(), contracts).await {
+    let upgrade = if args.dry_run {
+        init::UpgradeMode::DryRun
+    } else if args.upgrade_contract {
+        init::UpgradeMode::Upgrade
+    } else {
+        init::UpgradeMode::Refuse
+    };
+    match init::init_node(
+        node_client.clone(),
+        indexer_client.clone(),
+        contracts,
+        upgrade,
+    )
+    .await
+    {
         Ok(_) => {}
         Err(e) => {
             error!("Error initializing node: {:?}", e);
             return Ok(());
         }
     }
+    if args.dry_run {
+        return Ok(());
+    }
     let bus = SharedMessageBus::new(BusMetrics::global(config.id.clone()));
 
     std::fs::create_dir_all(&config.data_directory).context("creating data directory")?;
@@ -90,25 +210,45 @@ async fn main() -> Result<()> {
         openapi: Default::default(),
     });
 
+    for token in &config.tokens {
+        handler
+            .build_module::<ContractStateIndexer<TokenHistory, Vec<HistoryEvent>>>(
+                ContractStateIndexerCtx {
+                    contract_name: token.name.clone().into(),
+                    data_directory: config.data_directory.clone(),
+                    api: api_ctx.clone(),
+                },
+            )
+            .await?;
+    }
+    let indexer_url = format!(
+        "http://localhost:{}/v1/indexer/contract",
+        config.rest_server_port
+    );
+
     let app_ctx = Arc::new(AppModuleCtx {
         api: api_ctx.clone(),
         node_client,
         wallet_cn: wallet_cn.clone(),
+        relay_timeout: Duration::from_secs(config.relay_timeout_secs),
+        indexer_url: indexer_url.clone(),
+        histories: HistoryIndexers {
+            http: reqwest::Client::new(),
+            indexer_url,
+            tokens: config
+                .tokens
+                .iter()
+                .map(|token| token.name.clone().into())
+                .collect(),
+        },
     });
 
     handler.build_module::<AppModule>(app_ctx.clone()).await?;
 
     handler
-        .build_module::<ContractStateIndexer<Wallet, WalletEvent>>(ContractStateIndexerCtx {
-            contract_name: wallet_cn.clone(),
-            data_directory: config.data_directory.clone(),
-            api: api_ctx.clone(),
-        })
-        .await?;
-    handler
-        .build_module::<ContractStateIndexer<HyllarHistory, Vec<HistoryEvent>>>(
+        .build_module::<ContractStateIndexer<WalletProvableState, WalletEvent>>(
             ContractStateIndexerCtx {
-                contract_name: "oranj".into(),
+                contract_name: wallet_cn.clone(),
                 data_directory: config.data_directory.clone(),
                 api: api_ctx.clone(),
             },
@@ -116,9 +256,9 @@ async fn main() -> Result<()> {
         .await?;
 
     handler
-        .build_module::<AutoProver<Wallet>>(Arc::new(AutoProverCtx {
+        .build_module::<AutoProver<WalletProvableState>>(Arc::new(AutoProverCtx {
             data_directory: config.data_directory.clone(),
-            prover: Arc::new(Risc0Prover::new(contracts::WALLET_ELF)),
+            prover: config.prover.prover::<Wallet>(contracts::WALLET_ELF),
             contract_name: wallet_cn.clone(),
             node: app_ctx.node_client.clone(),
             default_state: Default::default(),
@@ -126,19 +266,21 @@ async fn main() -> Result<()> {
             max_txs_per_proof: config.wallet_max_txs_per_proof,
         }))
         .await?;
-    handler
-        .build_module::<AutoProver<SmtTokenProvableState>>(Arc::new(AutoProverCtx {
-            data_directory: config.data_directory.clone(),
-            prover: Arc::new(Risc0Prover::new(
-                hyle_smt_token::client::tx_executor_handler::metadata::SMT_TOKEN_ELF,
-            )),
-            contract_name: "oranj".into(),
-            node: app_ctx.node_client.clone(),
-            default_state: Default::default(),
-            buffer_blocks: config.smt_buffer_blocks,
-            max_txs_per_proof: config.smt_max_txs_per_proof,
-        }))
-        .await?;
+    for token in config.tokens.iter().filter(|token| token.prove) {
+        handler
+            .build_module::<AutoProver<SmtTokenProvableState>>(Arc::new(AutoProverCtx {
+                data_directory: config.data_directory.clone(),
+                prover: config.prover.prover::<SmtToken>(
+                    hyle_smt_token::client::tx_executor_handler::metadata::SMT_TOKEN_ELF,
+                ),
+                contract_name: token.name.clone().into(),
+                node: app_ctx.node_client.clone(),
+                default_state: Default::default(),
+                buffer_blocks: config.smt_buffer_blocks,
+                max_txs_per_proof: config.smt_max_txs_per_proof,
+            }))
+            .await?;
+    }
 
     handler
         .build_module::<WebSocketModule<AppWsInMessage, AppOutWsEvent>>(config.websocket.clone())
@@ -215,3 +357,28 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Registers the root of the accounts, with a snapshot of them for the indexers and provers.
+/// Once the wallet commits a root, the accounts are the ones persisted by the wallet indexer.
+fn wallet_state_upgrade(
+    data_directory: PathBuf,
+    wallet_cn: ContractName,
+) -> Box<dyn Fn(&[u8]) -> Result<init::StateUpgrade> + Send + Sync> {
+    Box::new(move |state_commitment| {
+        let state = WalletProvableState::upgraded(state_commitment, || {
+            // As saved by the contract state indexer
+            let file =
+                data_directory.join(format!("state_indexer_{}.bin", wallet_cn.0).replace(':', "_"));
+            let bytes = std::fs::read(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            borsh::from_slice::<Store<WalletProvableState>>(&bytes)
+                .with_context(|| format!("Failed to decode {}", file.display()))?
+                .state
+                .context("The wallet indexer has no state yet")
+        })?;
+        Ok(init::StateUpgrade {
+            state_commitment: sdk::StateCommitment(state.root().to_vec()),
+            constructor_metadata: borsh::to_vec(&state)?,
+        })
+    })
+}
@@ -0,0 +1,114 @@
+use std::{future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+use borsh::BorshDeserialize;
+use client_sdk::helpers::{risc0::Risc0Prover, ClientSdkProver};
+use sdk::{Calldata, ProofData, ZkContract};
+
+use crate::conf::ProverBackend;
+
+pub type Prover = Arc<dyn ClientSdkProver<Vec<Calldata>> + Send + Sync>;
+
+impl ProverBackend {
+    /// Prover of the contract `C`, whose guest program is `elf`
+    pub fn prover<C>(&self, elf: &'static [u8]) -> Prover
+    where
+        C: ZkContract + BorshDeserialize + Send + Sync + 'static,
+    {
+        match self {
+            ProverBackend::Risc0 | ProverBackend::Risc0Dev => Arc::new(Risc0Prover::new(elf)),
+            ProverBackend::Mock => Arc::new(MockProver::<C>::default()),
+        }
+    }
+}
+
+/// Executes the contract natively, as its guest would, and returns its outputs as the proof.
+/// Only accepted by the `test` verifier of a node.
+pub struct MockProver<C> {
+    _contract: PhantomData<fn() -> C>,
+}
+
+impl<C> Default for MockProver<C> {
+    fn default() -> Self {
+        Self {
+            _contract: PhantomData,
+        }
+    }
+}
+
+impl<C> ClientSdkProver<Vec<Calldata>> for MockProver<C>
+where
+    C: ZkContract + BorshDeserialize + 'static,
+{
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldatas: Vec<Calldata>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<ProofData>> + Send + '_>> {
+        Box::pin(async move {
+            let outputs = sdk::guest::execute::<C>(&commitment_metadata, &calldatas);
+            Ok(ProofData(borsh::to_vec(&outputs)?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use client_sdk::transaction_builder::TxExecutorHandler;
+    use sdk::{
+        hyle_model_utils::TimestampMs, Blob, BlobData, BlobIndex, HyleOutput, TxContext, TxHash,
+    };
+    use wallet::{
+        client::tx_executor_handler::WalletProvableState, AuthMethod, Wallet, WalletAction,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_prover_proves_wallet_transactions() {
+        let register = WalletAction::RegisterIdentity {
+            account: "bob".to_string(),
+            nonce: 0,
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(b"bob's password"),
+            },
+        };
+        let blobs = vec![
+            Blob {
+                contract_name: "check_secret".into(),
+                data: BlobData(b"bob's password".to_vec()),
+            },
+            register.as_blob("wallet".into()),
+        ];
+        let calldata = Calldata {
+            identity: "bob@wallet".into(),
+            index: BlobIndex(1),
+            tx_blob_count: blobs.len(),
+            blobs: blobs.clone().into(),
+            tx_hash: TxHash::default(),
+            tx_ctx: Some(TxContext {
+                timestamp: TimestampMs(1_000),
+                ..Default::default()
+            }),
+            private_input: vec![],
+        };
+
+        let mut state = WalletProvableState::default();
+        let commitment_metadata = state
+            .build_commitment_metadata(&blobs[1])
+            .expect("Failed to build commitment metadata");
+        let executed = state.handle(&calldata).expect("Failed to execute");
+        assert!(executed.success);
+
+        assert_eq!(ProverBackend::Mock.verifier(), "test");
+        let proof = ProverBackend::Mock
+            .prover::<Wallet>(&[])
+            .prove(commitment_metadata, vec![calldata])
+            .await
+            .expect("Failed to prove");
+        let outputs: Vec<HyleOutput> = borsh::from_slice(&proof.0).expect("Invalid proof");
+        assert_eq!(outputs.len(), 1);
+        assert!(outputs[0].success);
+        assert_eq!(outputs[0].initial_state, executed.initial_state);
+        assert_eq!(outputs[0].next_state, executed.next_state);
+    }
+}
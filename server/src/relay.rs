@@ -0,0 +1,481 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use axum::{
+    extract::{Json, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use client_sdk::{contract_indexer::AppError, rest_client::NodeApiClient};
+use hyle_modules::{
+    bus::{BusClientReceiver, SharedMessageBus},
+    module_bus_client,
+    node_state::module::NodeStateEvent,
+};
+use hyle_smt_token::SmtTokenAction;
+use k256::ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey};
+use sdk::{
+    verifiers::Secp256k1Blob, Blob, BlobData, BlobTransaction, ContractName, Identity, TxHash,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wallet::WalletAction;
+
+use crate::app::RouterCtx;
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct RelayBusClient {
+    receiver(NodeStateEvent),
+}
+}
+
+/// Session key authentication of a relayed transaction.
+/// `x-signature` is the session key's signature of the sha256 of `x-nonce`, as for `UseSessionKey`.
+#[derive(Debug, Clone)]
+pub struct AuthHeaders {
+    pub account: String,
+    pub public_key: [u8; 33],
+    pub signature: [u8; 64],
+    pub nonce: u128,
+}
+
+impl AuthHeaders {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| bad_request(anyhow!("Missing {name} header")))
+        };
+        let hex_header = |name: &str| {
+            hex::decode(header(name)?.trim_start_matches("0x"))
+                .map_err(|_| bad_request(anyhow!("Invalid {name} header")))
+        };
+
+        Ok(AuthHeaders {
+            account: header("x-account")?.to_string(),
+            public_key: hex_header("x-public-key")?
+                .try_into()
+                .map_err(|_| bad_request(anyhow!("Invalid x-public-key header")))?,
+            signature: hex_header("x-signature")?
+                .try_into()
+                .map_err(|_| bad_request(anyhow!("Invalid x-signature header")))?,
+            nonce: header("x-nonce")?
+                .parse()
+                .map_err(|_| bad_request(anyhow!("Invalid x-nonce header")))?,
+        })
+    }
+
+    /// Checks the signature of the nonce by the session key, before relaying the transaction.
+    /// The wallet contract then checks that the key is a session key of the account.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let key = VerifyingKey::from_sec1_bytes(&self.public_key)
+            .map_err(|_| anyhow!("Invalid public key"))?;
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| anyhow!("Invalid signature"))?;
+        key.verify_prehash(
+            &Sha256::digest(self.nonce.to_string().as_bytes()),
+            &signature,
+        )
+        .map_err(|_| anyhow!("Invalid signature"))
+    }
+
+    /// The transaction of the account relaying `blobs` after the session key authentication
+    pub fn transaction(&self, wallet_cn: &ContractName, blobs: Vec<Blob>) -> BlobTransaction {
+        BlobTransaction::new(self.identity(wallet_cn), self.blobs(wallet_cn, blobs))
+    }
+
+    pub fn identity(&self, wallet_cn: &ContractName) -> Identity {
+        Identity(format!("{}@{}", self.account, wallet_cn.0))
+    }
+
+    /// The blobs authenticating the transaction with the session key, followed by `blobs`
+    pub fn blobs(&self, wallet_cn: &ContractName, blobs: Vec<Blob>) -> Vec<Blob> {
+        let secp256k1 = Secp256k1Blob {
+            identity: self.identity(wallet_cn),
+            data: Sha256::digest(self.nonce.to_string().as_bytes()).into(),
+            public_key: self.public_key,
+            signature: self.signature,
+        };
+        let use_session_key = WalletAction::UseSessionKey {
+            account: self.account.clone(),
+            nonce: self.nonce,
+        };
+        let mut tx_blobs = vec![
+            Blob {
+                contract_name: ContractName("secp256k1".to_string()),
+                data: BlobData(borsh::to_vec(&secp256k1).expect("Failed to encode Secp256k1Blob")),
+            },
+            use_session_key.as_blob(wallet_cn.clone()),
+        ];
+        tx_blobs.extend(blobs);
+        tx_blobs
+    }
+}
+
+fn bad_request(error: anyhow::Error) -> AppError {
+    AppError(StatusCode::BAD_REQUEST, error)
+}
+
+/// Progress of a relayed transaction when the response is sent
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum RelayStatus {
+    /// Not in a block before the timeout
+    Submitted,
+    /// In a block, but not settled before the timeout
+    Sequenced,
+    Success,
+    Failed,
+    TimedOut,
+}
+
+impl RelayStatus {
+    /// Status code of the relay endpoints' response: `200 OK` once the transaction succeeded,
+    /// `202 Accepted` while it is still pending, and `422 Unprocessable Entity` if it failed or timed out
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            RelayStatus::Success => StatusCode::OK,
+            RelayStatus::Submitted | RelayStatus::Sequenced => StatusCode::ACCEPTED,
+            RelayStatus::Failed | RelayStatus::TimedOut => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayResponse {
+    pub tx_hash: TxHash,
+    pub status: RelayStatus,
+}
+
+/// Blobs to send along the session key authentication, e.g. token actions
+#[derive(Debug, Deserialize)]
+pub struct SendRequest {
+    pub blobs: Vec<Blob>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    pub token: ContractName,
+    pub recipient: Identity,
+    pub amount: u128,
+}
+
+/// Relays the blobs after the session key authentication, see [`relay`] for the response
+pub async fn send(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<SendRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::from_headers(&headers)?;
+    auth.verify().map_err(bad_request)?;
+    relay(&ctx, auth.transaction(&ctx.wallet_cn, request.blobs)).await
+}
+
+/// Relays a token transfer from the account, see [`relay`] for the response
+pub async fn transfer(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<TransferRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::from_headers(&headers)?;
+    auth.verify().map_err(bad_request)?;
+    let transfer = SmtTokenAction::Transfer {
+        sender: auth.identity(&ctx.wallet_cn),
+        recipient: request.recipient,
+        amount: request.amount,
+    };
+    let blob = Blob {
+        contract_name: request.token,
+        data: BlobData(borsh::to_vec(&transfer).expect("Failed to encode SmtTokenAction")),
+    };
+    relay(&ctx, auth.transaction(&ctx.wallet_cn, vec![blob])).await
+}
+
+/// Submits `tx`, responding with its [`RelayResponse`] once it settles or after the relay timeout,
+/// with the [`RelayStatus::status_code`] of its status. `400 Bad Request` if the node rejects it.
+async fn relay(ctx: &RouterCtx, tx: BlobTransaction) -> Result<impl IntoResponse, AppError> {
+    let response = submit_and_wait(&ctx.bus, ctx.node_client.as_ref(), tx, ctx.relay_timeout)
+        .await
+        .map_err(bad_request)?;
+    Ok((response.status.status_code(), Json(response)))
+}
+
+/// Submits `tx` to the node, then follows it in the blocks until it settles or `timeout` expires
+pub async fn submit_and_wait(
+    bus: &SharedMessageBus,
+    node: &impl NodeApiClient,
+    tx: BlobTransaction,
+    timeout: Duration,
+) -> anyhow::Result<RelayResponse> {
+    // Listen before submitting, not to miss the block of the transaction
+    let mut bus = RelayBusClient::new_from_bus(bus.new_handle()).await;
+    let tx_hash = node
+        .send_tx_blob(tx)
+        .await
+        .context("Failed to submit transaction")?;
+
+    let mut response = RelayResponse {
+        tx_hash: tx_hash.clone(),
+        status: RelayStatus::Submitted,
+    };
+    let settled = tokio::time::timeout(timeout, async {
+        loop {
+            let NodeStateEvent::NewBlock(block) = bus.recv().await?;
+            if block.txs.iter().any(|(tx_id, _)| tx_id.1 == tx_hash) {
+                response.status = RelayStatus::Sequenced;
+            }
+            if block.successful_txs.contains(&tx_hash) {
+                response.status = RelayStatus::Success;
+            } else if block.failed_txs.contains(&tx_hash) {
+                response.status = RelayStatus::Failed;
+            } else if block.timed_out_txs.contains(&tx_hash) {
+                response.status = RelayStatus::TimedOut;
+            } else {
+                continue;
+            }
+            return anyhow::Ok(());
+        }
+    })
+    .await;
+    if let Ok(res) = settled {
+        res?;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{http::HeaderValue, Router};
+    use client_sdk::rest_client::NodeApiHttpClient;
+    use hyle_modules::bus::{metrics::BusMetrics, BusClientSender};
+    use k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey};
+    use sdk::{Block, DataProposalHash, Hashed, TxId};
+
+    use super::*;
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        values
+            .iter()
+            .map(|(name, value)| {
+                let value = HeaderValue::from_str(value).expect("Invalid header value");
+                (axum::http::HeaderName::from_static(name), value)
+            })
+            .collect()
+    }
+
+    fn valid_headers() -> Vec<(&'static str, String)> {
+        vec![
+            ("x-account", "bob".to_string()),
+            ("x-public-key", format!("0x{}", hex::encode([2; 33]))),
+            ("x-signature", hex::encode([3; 64])),
+            ("x-nonce", "42".to_string()),
+        ]
+    }
+
+    /// The error message of the headers with `name` replaced by `value`, or removed
+    fn header_error(name: &str, value: Option<&str>) -> String {
+        let values = valid_headers();
+        let values: Vec<_> = values
+            .iter()
+            .filter_map(|(header, valid)| {
+                if *header == name {
+                    value.map(|value| (*header, value))
+                } else {
+                    Some((*header, valid.as_str()))
+                }
+            })
+            .collect();
+        let AppError(status, error) =
+            AuthHeaders::from_headers(&headers(&values)).expect_err("Headers accepted");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        error.to_string()
+    }
+
+    #[test]
+    fn auth_headers_are_parsed() {
+        let values = valid_headers();
+        let values: Vec<_> = values
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        let auth = AuthHeaders::from_headers(&headers(&values)).expect("Invalid headers");
+        assert_eq!(auth.account, "bob");
+        assert_eq!(auth.public_key, [2; 33]);
+        assert_eq!(auth.signature, [3; 64]);
+        assert_eq!(auth.nonce, 42);
+        assert_eq!(
+            auth.identity(&"wallet".into()),
+            Identity::from("bob@wallet")
+        );
+    }
+
+    #[test]
+    fn missing_or_malformed_auth_headers_are_rejected() {
+        for name in ["x-account", "x-public-key", "x-signature", "x-nonce"] {
+            assert_eq!(header_error(name, None), format!("Missing {name} header"));
+        }
+        assert_eq!(
+            header_error("x-public-key", Some("0xzz")),
+            "Invalid x-public-key header"
+        );
+        // Not a compressed public key
+        assert_eq!(
+            header_error("x-public-key", Some(&hex::encode([2; 65]))),
+            "Invalid x-public-key header"
+        );
+        assert_eq!(
+            header_error("x-signature", Some(&hex::encode([3; 63]))),
+            "Invalid x-signature header"
+        );
+        assert_eq!(
+            header_error("x-nonce", Some("-1")),
+            "Invalid x-nonce header"
+        );
+    }
+
+    #[test]
+    fn auth_signature_is_verified() {
+        let key = SigningKey::from_slice(&[7; 32]).expect("Invalid session key");
+        let signature: Signature = key
+            .sign_prehash(&Sha256::digest(b"42"))
+            .expect("Failed to sign");
+        let mut auth = AuthHeaders {
+            account: "bob".to_string(),
+            public_key: key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .try_into()
+                .expect("Not a compressed key"),
+            signature: signature
+                .to_bytes()
+                .as_slice()
+                .try_into()
+                .expect("Not a signature"),
+            nonce: 42,
+        };
+        auth.verify().expect("Valid signature rejected");
+
+        // Signed for another nonce
+        auth.nonce = 43;
+        assert_eq!(
+            auth.verify().expect_err("Signature accepted").to_string(),
+            "Invalid signature"
+        );
+        // Not a public key
+        auth.nonce = 42;
+        auth.public_key = [0; 33];
+        assert_eq!(
+            auth.verify().expect_err("Signature accepted").to_string(),
+            "Invalid public key"
+        );
+    }
+
+    module_bus_client! {
+    #[derive(Debug)]
+    struct BlockBusClient {
+        sender(NodeStateEvent),
+    }
+    }
+
+    /// Serves the node's routes, accepting any transaction then publishing the `blocks` built for it,
+    /// as the node state does once they are received from the DA
+    async fn start_node(
+        bus: &SharedMessageBus,
+        blocks: fn(&TxHash) -> Vec<Block>,
+    ) -> NodeApiHttpClient {
+        let bus = Arc::new(bus.new_handle());
+        let app = Router::new().fallback(move |Json(tx): Json<BlobTransaction>| {
+            let bus = bus.clone();
+            async move {
+                let tx_hash = tx.hashed();
+                let mut sender = BlockBusClient::new_from_bus(bus.new_handle()).await;
+                for block in blocks(&tx_hash) {
+                    sender
+                        .send(NodeStateEvent::NewBlock(Box::new(block)))
+                        .expect("Failed to send block");
+                }
+                Json(tx_hash)
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind node");
+        let url = format!("http://{}", listener.local_addr().expect("No node address"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        NodeApiHttpClient::new(url).expect("Invalid node URL")
+    }
+
+    fn sequenced(tx_hash: &TxHash) -> Block {
+        let tx = BlobTransaction::new(Identity::from("bob@wallet"), vec![]);
+        Block {
+            txs: vec![(
+                TxId(DataProposalHash::default(), tx_hash.clone()),
+                tx.into(),
+            )],
+            ..Default::default()
+        }
+    }
+
+    async fn relayed_status(blocks: fn(&TxHash) -> Vec<Block>) -> RelayStatus {
+        let bus = SharedMessageBus::new(BusMetrics::global("test".to_string()));
+        let node = start_node(&bus, blocks).await;
+        let tx = BlobTransaction::new(Identity::from("bob@wallet"), vec![]);
+        let response = submit_and_wait(&bus, &node, tx.clone(), Duration::from_millis(200))
+            .await
+            .expect("Failed to relay");
+        assert_eq!(response.tx_hash, tx.hashed());
+        response.status
+    }
+
+    #[tokio::test]
+    async fn relayed_transaction_settles() {
+        let status = relayed_status(|tx_hash| {
+            vec![
+                Block::default(),
+                sequenced(tx_hash),
+                Block {
+                    successful_txs: vec![tx_hash.clone()],
+                    ..Default::default()
+                },
+            ]
+        })
+        .await;
+        assert_eq!(status, RelayStatus::Success);
+        assert_eq!(status.status_code(), StatusCode::OK);
+
+        let status = relayed_status(|tx_hash| {
+            vec![Block {
+                failed_txs: vec![tx_hash.clone()],
+                ..Default::default()
+            }]
+        })
+        .await;
+        assert_eq!(status, RelayStatus::Failed);
+        assert_eq!(status.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let status = relayed_status(|tx_hash| {
+            vec![Block {
+                timed_out_txs: vec![tx_hash.clone()],
+                ..Default::default()
+            }]
+        })
+        .await;
+        assert_eq!(status, RelayStatus::TimedOut);
+        assert_eq!(status.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn relay_timeout_returns_the_progress() {
+        let status = relayed_status(|_| vec![]).await;
+        assert_eq!(status, RelayStatus::Submitted);
+        assert_eq!(status.status_code(), StatusCode::ACCEPTED);
+
+        let status = relayed_status(|tx_hash| vec![sequenced(tx_hash)]).await;
+        assert_eq!(status, RelayStatus::Sequenced);
+        assert_eq!(status.status_code(), StatusCode::ACCEPTED);
+    }
+}
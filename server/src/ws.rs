@@ -0,0 +1,1005 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use client_sdk::rest_client::NodeApiHttpClient;
+use hyle_modules::{
+    bus::{BusClientSender, SharedMessageBus},
+    module_bus_client,
+    modules::websocket::WsTopicMessage,
+};
+use k256::ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey};
+use reqwest::StatusCode;
+use sdk::{Blob, ContractName, Identity};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{
+    app::AppOutWsEvent,
+    history,
+    relay::{self, AuthHeaders, RelayResponse, RelayStatus},
+};
+
+/// Command sent by a WebSocket client. Replies are published on the `client_id` topic, which the
+/// client registers first, as an [`AppOutWsEvent::Response`] with the same `request_id`.
+/// `client_id` must be unguessable, as anyone registering it receives the events of its subscriptions.
+/// It belongs to the connection that first used it: commands of other connections using it are dropped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsCommand {
+    pub client_id: String,
+    pub request_id: u64,
+    pub request: WsRequest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WsRequest {
+    /// A challenge to sign to subscribe to an account. Valid for one subscription, within [`CHALLENGE_TTL`].
+    Challenge,
+    /// Receive the events of `account` on the `client_id` topic. `signature` (hex) is the signature,
+    /// by the session key `public_key` (hex) of the account, of the sha256 of the challenge.
+    Subscribe {
+        account: String,
+        public_key: String,
+        signature: String,
+    },
+    Unsubscribe {
+        account: String,
+    },
+    /// Requires a subscription of the connection to `account`
+    GetAccountInfo {
+        account: String,
+    },
+    /// Requires a subscription of the connection to `account`
+    GetHistory {
+        account: String,
+    },
+    /// Relays `blobs` after the session key authentication, as `POST /api/send` does, replied to once
+    /// the transaction settles. `signature` (hex) is the signature, by the session key `public_key` (hex)
+    /// of the account, of the sha256 of `nonce`, as for `UseSessionKey`.
+    SubmitTx {
+        account: String,
+        public_key: String,
+        signature: String,
+        nonce: u128,
+        blobs: Vec<Blob>,
+    },
+    /// Keeps the connection's subscriptions alive, see [`CONNECTION_TTL`]
+    Ping,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum WsReply {
+    Challenge {
+        challenge: String,
+    },
+    Subscribed {
+        account: String,
+    },
+    Unsubscribed {
+        account: String,
+    },
+    /// As returned by the wallet indexer's `/account/{account}` route
+    AccountInfo(serde_json::Value),
+    /// First page of the history across tokens, as returned by the `/history/{account}` route
+    History(serde_json::Value),
+    TxSettled(RelayResponse),
+    Pong,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum WsError {
+    /// A challenge must be requested before each subscription, and used within [`CHALLENGE_TTL`]
+    NoChallenge,
+    InvalidSignature,
+    /// The key is not a valid session key of the account
+    InvalidSessionKey,
+    AlreadySubscribed {
+        account: String,
+    },
+    NotSubscribed {
+        account: String,
+    },
+    NotFound {
+        message: String,
+    },
+    /// The node refused the transaction
+    TxRejected {
+        message: String,
+    },
+    /// The transaction did not settle successfully, `status` is where it stopped
+    TxNotSettled {
+        tx_hash: String,
+        status: RelayStatus,
+    },
+    Internal {
+        message: String,
+    },
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct WsReplyBusClient {
+    sender(WsTopicMessage<AppOutWsEvent>),
+}
+}
+
+/// What the commands need to be answered outside of the app module's loop
+#[derive(Clone)]
+pub struct WsCommandCtx {
+    pub bus: Arc<SharedMessageBus>,
+    pub node_client: Arc<NodeApiHttpClient>,
+    pub http: reqwest::Client,
+    /// Base URL of the contract indexers' routes
+    pub indexer_url: String,
+    pub wallet_cn: ContractName,
+    pub relay_timeout: Duration,
+    pub histories: history::HistoryIndexers,
+    pub subscriptions: Arc<Mutex<Subscriptions>>,
+}
+
+impl WsCommandCtx {
+    /// Answers the command of the connection `addr`, then replies on the bus
+    pub async fn answer(self, addr: String, command: WsCommand) -> anyhow::Result<()> {
+        if !self
+            .subscriptions()
+            .connect(&addr, &command.client_id, Instant::now())
+        {
+            warn!(
+                "Dropping command of {addr}: client id {} belongs to another connection",
+                command.client_id
+            );
+            return Ok(());
+        }
+        let result = match command.request {
+            WsRequest::Challenge => Ok(WsReply::Challenge {
+                challenge: self.subscriptions().challenge(&addr, Instant::now()),
+            }),
+            WsRequest::Subscribe {
+                account,
+                public_key,
+                signature,
+            } => {
+                self.subscribe(&addr, account, &public_key, &signature)
+                    .await
+            }
+            WsRequest::Unsubscribe { account } => {
+                let identity = self.identity(&account);
+                if self.subscriptions().unsubscribe(&addr, &identity) {
+                    Ok(WsReply::Unsubscribed { account })
+                } else {
+                    Err(WsError::NotSubscribed { account })
+                }
+            }
+            WsRequest::GetAccountInfo { account } => self.account_info(&addr, account).await,
+            WsRequest::GetHistory { account } => self.history(&addr, account).await,
+            WsRequest::SubmitTx {
+                account,
+                public_key,
+                signature,
+                nonce,
+                blobs,
+            } => self
+                .submit(account, &public_key, &signature, nonce, blobs)
+                .await
+                .map(WsReply::TxSettled),
+            WsRequest::Ping => Ok(WsReply::Pong),
+        };
+        let mut bus = WsReplyBusClient::new_from_bus(self.bus.new_handle()).await;
+        bus.send(reply(command.client_id, command.request_id, result))?;
+        Ok(())
+    }
+
+    fn subscriptions(&self) -> std::sync::MutexGuard<'_, Subscriptions> {
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Events of an account are published for its identity on the wallet contract
+    fn identity(&self, account: &str) -> String {
+        format!("{account}@{}", self.wallet_cn.0)
+    }
+
+    /// Account-scoped requests are only answered to connections subscribed to the account,
+    /// having proved they control one of its session keys
+    fn authenticate(&self, addr: &str, account: &str) -> Result<(), WsError> {
+        if self
+            .subscriptions()
+            .is_subscribed(addr, &self.identity(account))
+        {
+            Ok(())
+        } else {
+            Err(WsError::NotSubscribed {
+                account: account.to_string(),
+            })
+        }
+    }
+
+    async fn account_info(&self, addr: &str, account: String) -> Result<WsReply, WsError> {
+        self.authenticate(addr, &account)?;
+        self.get(&format!("{}/account/{account}", self.wallet_cn.0))
+            .await
+            .map(WsReply::AccountInfo)
+    }
+
+    async fn history(&self, addr: &str, account: String) -> Result<WsReply, WsError> {
+        self.authenticate(addr, &account)?;
+        let history =
+            history::merged_history(&self.histories, Identity(account), &Default::default())
+                .await
+                .map_err(|e| WsError::Internal {
+                    message: e.to_string(),
+                })?;
+        serde_json::to_value(history)
+            .map(WsReply::History)
+            .map_err(|e| WsError::Internal {
+                message: e.to_string(),
+            })
+    }
+
+    /// Subscribes the connection once it proved it controls a session key of the account
+    async fn subscribe(
+        &self,
+        addr: &str,
+        account: String,
+        public_key: &str,
+        signature: &str,
+    ) -> Result<WsReply, WsError> {
+        let challenge = self
+            .subscriptions()
+            .take_challenge(addr, Instant::now())
+            .ok_or(WsError::NoChallenge)?;
+        verify_signature(&challenge, public_key, signature)?;
+        // Session keys are stored as lowercase hex
+        let public_key = public_key.to_lowercase();
+
+        let info = self
+            .get(&format!("{}/account/{account}", self.wallet_cn.0))
+            .await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let valid_key = info["session_keys"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|key| {
+                key["key"].as_str() == Some(public_key.as_str())
+                    && key["expiration_date"]
+                        .as_u64()
+                        .is_some_and(|expiration| u128::from(expiration) > now)
+            });
+        if !valid_key {
+            return Err(WsError::InvalidSessionKey);
+        }
+
+        let identity = self.identity(&account);
+        if self.subscriptions().subscribe(addr, identity) {
+            Ok(WsReply::Subscribed { account })
+        } else {
+            Err(WsError::AlreadySubscribed { account })
+        }
+    }
+
+    async fn get(&self, route: &str) -> Result<serde_json::Value, WsError> {
+        let internal = |e: reqwest::Error| WsError::Internal {
+            message: e.to_string(),
+        };
+        let response = self
+            .http
+            .get(format!("{}/{route}", self.indexer_url))
+            .send()
+            .await
+            .map_err(internal)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(WsError::NotFound {
+                message: response.text().await.map_err(internal)?,
+            });
+        }
+        response
+            .error_for_status()
+            .map_err(internal)?
+            .json()
+            .await
+            .map_err(internal)
+    }
+
+    /// Relays `blobs` once the session key signature is checked, as the relay endpoints do
+    async fn submit(
+        &self,
+        account: String,
+        public_key: &str,
+        signature: &str,
+        nonce: u128,
+        blobs: Vec<Blob>,
+    ) -> Result<RelayResponse, WsError> {
+        let tx = session_key_auth(account, public_key, signature, nonce)?
+            .transaction(&self.wallet_cn, blobs);
+        let response =
+            relay::submit_and_wait(&self.bus, self.node_client.as_ref(), tx, self.relay_timeout)
+                .await
+                .map_err(|e| WsError::TxRejected {
+                    message: e.root_cause().to_string(),
+                })?;
+        match response.status {
+            RelayStatus::Success => Ok(response),
+            status => Err(WsError::TxNotSettled {
+                tx_hash: response.tx_hash.0,
+                status,
+            }),
+        }
+    }
+}
+
+/// Reply to the request `request_id` of `client_id`
+pub fn reply(
+    client_id: String,
+    request_id: u64,
+    result: Result<WsReply, WsError>,
+) -> WsTopicMessage<AppOutWsEvent> {
+    WsTopicMessage::new(client_id, AppOutWsEvent::Response { request_id, result })
+}
+
+/// Checks that `signature` is the signature of the sha256 of `challenge` by `public_key`,
+/// both hex encoded, as a session key signs the nonce of a transaction
+fn verify_signature(challenge: &str, public_key: &str, signature: &str) -> Result<(), WsError> {
+    let public_key = hex::decode(public_key).map_err(|_| WsError::InvalidSignature)?;
+    let signature = hex::decode(signature).map_err(|_| WsError::InvalidSignature)?;
+    let key = VerifyingKey::from_sec1_bytes(&public_key).map_err(|_| WsError::InvalidSignature)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| WsError::InvalidSignature)?;
+    key.verify_prehash(&Sha256::digest(challenge.as_bytes()), &signature)
+        .map_err(|_| WsError::InvalidSignature)
+}
+
+/// The session key authentication of a [`WsRequest::SubmitTx`]
+fn session_key_auth(
+    account: String,
+    public_key: &str,
+    signature: &str,
+    nonce: u128,
+) -> Result<AuthHeaders, WsError> {
+    let decode = |value: &str| hex::decode(value.trim_start_matches("0x")).ok();
+    let auth = AuthHeaders {
+        account,
+        public_key: decode(public_key)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(WsError::InvalidSignature)?,
+        signature: decode(signature)
+            .and_then(|signature| signature.try_into().ok())
+            .ok_or(WsError::InvalidSignature)?,
+        nonce,
+    };
+    auth.verify().map_err(|_| WsError::InvalidSignature)?;
+    Ok(auth)
+}
+
+/// How long a challenge can be answered
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+/// How long the state of a connection is kept after its last command. The WebSocket module does not
+/// tell when a socket closes: clients [`WsRequest::Ping`] more often than this to stay subscribed.
+pub const CONNECTION_TTL: Duration = Duration::from_secs(90);
+
+/// State of a WebSocket connection, keyed by its address
+#[derive(Debug)]
+struct Connection {
+    /// Topic the connection receives its replies and events on
+    client_id: String,
+    /// Pending challenge, with when it was issued
+    challenge: Option<(String, Instant)>,
+    /// Identities the connection is subscribed to
+    identities: BTreeSet<String>,
+    last_seen: Instant,
+}
+
+/// Accounts whose events the connections receive, once authenticated
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    connections: BTreeMap<String, Connection>,
+    /// Connection each client id belongs to
+    client_ids: BTreeMap<String, String>,
+    /// Connections subscribed to an identity, by identity
+    subscribers: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Subscriptions {
+    /// Records a command of the connection `addr` using `client_id`. Returns false if either
+    /// is already bound to another one, keeping a client id from being taken over.
+    pub fn connect(&mut self, addr: &str, client_id: &str, now: Instant) -> bool {
+        if let Some(connection) = self.connections.get_mut(addr) {
+            connection.last_seen = now;
+            return connection.client_id == client_id;
+        }
+        if self.client_ids.contains_key(client_id) {
+            return false;
+        }
+        self.client_ids
+            .insert(client_id.to_string(), addr.to_string());
+        self.connections.insert(
+            addr.to_string(),
+            Connection {
+                client_id: client_id.to_string(),
+                challenge: None,
+                identities: BTreeSet::new(),
+                last_seen: now,
+            },
+        );
+        true
+    }
+
+    /// Issues a new challenge to the connection, replacing its pending one
+    pub fn challenge(&mut self, addr: &str, now: Instant) -> String {
+        let challenge = hex::encode(rand::random::<[u8; 32]>());
+        if let Some(connection) = self.connections.get_mut(addr) {
+            connection.challenge = Some((challenge.clone(), now));
+        }
+        challenge
+    }
+
+    /// The pending challenge of the connection, if issued less than [`CHALLENGE_TTL`] ago
+    pub fn take_challenge(&mut self, addr: &str, now: Instant) -> Option<String> {
+        let (challenge, issued_at) = self.connections.get_mut(addr)?.challenge.take()?;
+        (now.duration_since(issued_at) < CHALLENGE_TTL).then_some(challenge)
+    }
+
+    /// Returns false if the connection was already subscribed
+    pub fn subscribe(&mut self, addr: &str, identity: String) -> bool {
+        let Some(connection) = self.connections.get_mut(addr) else {
+            return false;
+        };
+        if !connection.identities.insert(identity.clone()) {
+            return false;
+        }
+        self.subscribers
+            .entry(identity)
+            .or_default()
+            .insert(addr.to_string());
+        true
+    }
+
+    pub fn is_subscribed(&self, addr: &str, identity: &str) -> bool {
+        self.connections
+            .get(addr)
+            .is_some_and(|connection| connection.identities.contains(identity))
+    }
+
+    /// Returns false if the connection was not subscribed
+    pub fn unsubscribe(&mut self, addr: &str, identity: &str) -> bool {
+        let Some(connection) = self.connections.get_mut(addr) else {
+            return false;
+        };
+        if !connection.identities.remove(identity) {
+            return false;
+        }
+        self.remove_subscriber(identity, addr);
+        true
+    }
+
+    fn remove_subscriber(&mut self, identity: &str, addr: &str) {
+        if let Some(subscribers) = self.subscribers.get_mut(identity) {
+            subscribers.remove(addr);
+            if subscribers.is_empty() {
+                self.subscribers.remove(identity);
+            }
+        }
+    }
+
+    /// Forgets the connections without a command for [`CONNECTION_TTL`], their sockets being
+    /// closed or their clients gone
+    pub fn prune(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| now.duration_since(connection.last_seen) >= CONNECTION_TTL)
+            .map(|(addr, _)| addr.clone())
+            .collect();
+        for addr in expired {
+            let Some(connection) = self.connections.remove(&addr) else {
+                continue;
+            };
+            self.client_ids.remove(&connection.client_id);
+            for identity in &connection.identities {
+                self.remove_subscriber(identity, &addr);
+            }
+        }
+    }
+
+    /// Topics of the connections subscribed to the events of `identity`
+    pub fn subscribers(&self, identity: &str) -> Vec<String> {
+        self.subscribers
+            .get(identity)
+            .into_iter()
+            .flatten()
+            .filter_map(|addr| self.connections.get(addr))
+            .map(|connection| connection.client_id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use axum::{extract::Path, routing::get, Json, Router};
+    use futures::{SinkExt, StreamExt};
+    use hyle_modules::{
+        bus::metrics::BusMetrics,
+        modules::{
+            contract_state_indexer::CSIBusEvent,
+            websocket::{WebSocketConfig, WebSocketModule},
+            BuildApiContextInner, ModulesHandler,
+        },
+    };
+    use k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey};
+    use serde_json::{json, Value};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+    use wallet::client::indexer::{WalletEvent, WalletNotification, WalletOutcome};
+
+    use super::*;
+    use crate::app::{AppModule, AppModuleCtx, AppWsInMessage};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    const ACCOUNT: &str = "bob";
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map(|addr| addr.port())
+            .expect("No free port")
+    }
+
+    fn session_key() -> SigningKey {
+        SigningKey::from_slice(&[7; 32]).expect("Invalid session key")
+    }
+
+    fn public_key(key: &SigningKey) -> String {
+        hex::encode(key.verifying_key().to_encoded_point(true).as_bytes())
+    }
+
+    fn sign(key: &SigningKey, challenge: &str) -> String {
+        let signature: Signature = key
+            .sign_prehash(&Sha256::digest(challenge.as_bytes()))
+            .expect("Failed to sign");
+        hex::encode(signature.to_bytes())
+    }
+
+    /// Serves the wallet indexer's account route, `bob` having the session key of [`session_key`]
+    async fn start_indexer() -> String {
+        let key = public_key(&session_key());
+        let app = Router::new().route(
+            "/wallet/account/{account}",
+            get(move |Path(account): Path<String>| async move {
+                Json(json!({
+                    "account": account,
+                    "session_keys": [{ "key": key, "expiration_date": u64::MAX, "nonce": 0 }],
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind indexer");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("No indexer address")
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    /// Runs the app and WebSocket modules, returning the bus and the WebSocket URL
+    async fn start_server() -> (SharedMessageBus, String) {
+        let indexer_url = start_indexer().await;
+        let bus = SharedMessageBus::new(BusMetrics::global("test".to_string()));
+        let mut handler = ModulesHandler::new(&bus).await;
+        let ctx = Arc::new(AppModuleCtx {
+            api: Arc::new(BuildApiContextInner {
+                router: std::sync::Mutex::new(Some(Router::new())),
+                openapi: Default::default(),
+            }),
+            node_client: Arc::new(
+                NodeApiHttpClient::new("http://127.0.0.1:1".to_string()).expect("Invalid node URL"),
+            ),
+            wallet_cn: "wallet".into(),
+            relay_timeout: Duration::from_secs(1),
+            indexer_url,
+            histories: Default::default(),
+        });
+        handler
+            .build_module::<AppModule>(ctx)
+            .await
+            .expect("Failed to build app module");
+        let port = free_port();
+        handler
+            .build_module::<WebSocketModule<AppWsInMessage, AppOutWsEvent>>(WebSocketConfig {
+                port,
+                ..Default::default()
+            })
+            .await
+            .expect("Failed to build WebSocket module");
+        tokio::spawn(async move { handler.start_modules().await });
+
+        let url = format!("ws://127.0.0.1:{port}/ws");
+        // Wait for the WebSocket server to listen
+        for _ in 0..50 {
+            if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        (bus, url)
+    }
+
+    async fn connect(url: &str, topic: &str) -> Client {
+        let (mut client, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .expect("Failed to connect");
+        client
+            .send(Message::text(json!({ "RegisterTopic": topic }).to_string()))
+            .await
+            .expect("Failed to register topic");
+        client
+    }
+
+    async fn send(client: &mut Client, client_id: &str, request_id: u64, request: WsRequest) {
+        let command = WsCommand {
+            client_id: client_id.to_string(),
+            request_id,
+            request,
+        };
+        client
+            .send(Message::text(json!({ "Message": command }).to_string()))
+            .await
+            .expect("Failed to send command");
+    }
+
+    /// Next message received, if any within a second
+    async fn next(client: &mut Client) -> Option<Value> {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(1), client.next())
+                .await
+                .ok()??
+                .expect("WebSocket error");
+            if let Message::Text(text) = message {
+                return Some(serde_json::from_str(&text).expect("Invalid message"));
+            }
+        }
+    }
+
+    async fn challenge(client: &mut Client, client_id: &str) -> String {
+        send(client, client_id, 0, WsRequest::Challenge).await;
+        let reply = next(client).await.expect("No challenge");
+        reply["Response"]["result"]["Ok"]["Challenge"]["challenge"]
+            .as_str()
+            .expect("Not a challenge")
+            .to_string()
+    }
+
+    module_bus_client! {
+    #[derive(Debug)]
+    struct WalletEventBusClient {
+        sender(CSIBusEvent<WalletEvent>),
+    }
+    }
+
+    /// Publishes an event of the account, as the wallet indexer does
+    async fn wallet_event(bus: &SharedMessageBus) {
+        let mut sender = WalletEventBusClient::new_from_bus(bus.new_handle()).await;
+        sender
+            .send(CSIBusEvent {
+                contract_name: "wallet".into(),
+                event: WalletEvent {
+                    account: Identity(format!("{ACCOUNT}@wallet")),
+                    outcome: WalletOutcome::Failed,
+                    notification: Some(WalletNotification::PasswordChanged {
+                        account: Identity(format!("{ACCOUNT}@wallet")),
+                    }),
+                },
+            })
+            .expect("Failed to send wallet event");
+    }
+
+    #[tokio::test]
+    async fn signed_subscription_receives_account_events() {
+        let (bus, url) = start_server().await;
+        let client_id = "client-1";
+        let mut client = connect(&url, client_id).await;
+        // Unauthenticated client listening on the account's topic
+        let mut eavesdropper = connect(&url, &format!("{ACCOUNT}@wallet")).await;
+
+        let key = session_key();
+        let challenge = challenge(&mut client, client_id).await;
+        send(
+            &mut client,
+            client_id,
+            1,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&key),
+                signature: sign(&key, &challenge),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(reply["Response"]["request_id"], 1);
+        assert_eq!(
+            reply["Response"]["result"]["Ok"]["Subscribed"]["account"],
+            ACCOUNT
+        );
+
+        wallet_event(&bus).await;
+        let notification = next(&mut client).await.expect("No notification");
+        assert_eq!(
+            notification["WalletNotification"]["PasswordChanged"]["account"],
+            format!("{ACCOUNT}@wallet")
+        );
+        let event = next(&mut client).await.expect("No event");
+        assert_eq!(event["WalletEvent"]["account"], format!("{ACCOUNT}@wallet"));
+        assert_eq!(next(&mut eavesdropper).await, None);
+    }
+
+    #[tokio::test]
+    async fn account_requests_require_a_subscription() {
+        let (_bus, url) = start_server().await;
+        let client_id = "client-4";
+        let mut client = connect(&url, client_id).await;
+
+        let requests = [
+            WsRequest::GetAccountInfo {
+                account: ACCOUNT.to_string(),
+            },
+            WsRequest::GetHistory {
+                account: ACCOUNT.to_string(),
+            },
+        ];
+        for (request_id, request) in (1..).zip(requests) {
+            send(&mut client, client_id, request_id, request).await;
+            let reply = next(&mut client).await.expect("No reply");
+            assert_eq!(reply["Response"]["result"]["Err"]["type"], "NotSubscribed");
+        }
+
+        let key = session_key();
+        let challenge = challenge(&mut client, client_id).await;
+        send(
+            &mut client,
+            client_id,
+            3,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&key),
+                signature: sign(&key, &challenge),
+            },
+        )
+        .await;
+        assert!(next(&mut client).await.is_some());
+
+        send(
+            &mut client,
+            client_id,
+            4,
+            WsRequest::GetAccountInfo {
+                account: ACCOUNT.to_string(),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(
+            reply["Response"]["result"]["Ok"]["AccountInfo"]["account"],
+            ACCOUNT
+        );
+        send(
+            &mut client,
+            client_id,
+            5,
+            WsRequest::GetHistory {
+                account: ACCOUNT.to_string(),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert!(reply["Response"]["result"]["Ok"]["History"].is_object());
+    }
+
+    #[tokio::test]
+    async fn subscription_requires_a_valid_signature() {
+        let (bus, url) = start_server().await;
+        let client_id = "client-2";
+        let mut client = connect(&url, client_id).await;
+        let key = session_key();
+        let other_key = SigningKey::from_slice(&[8; 32]).expect("Invalid key");
+
+        // No challenge requested
+        send(
+            &mut client,
+            client_id,
+            1,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&key),
+                signature: sign(&key, "challenge"),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(reply["Response"]["result"]["Err"]["type"], "NoChallenge");
+
+        // Signed by another key
+        let challenge = challenge(&mut client, client_id).await;
+        send(
+            &mut client,
+            client_id,
+            2,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&key),
+                signature: sign(&other_key, &challenge),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(
+            reply["Response"]["result"]["Err"]["type"],
+            "InvalidSignature"
+        );
+
+        // Not a session key of the account
+        let challenge = challenge(&mut client, client_id).await;
+        send(
+            &mut client,
+            client_id,
+            3,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&other_key),
+                signature: sign(&other_key, &challenge),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(
+            reply["Response"]["result"]["Err"]["type"],
+            "InvalidSessionKey"
+        );
+
+        // A challenge is only valid once
+        send(
+            &mut client,
+            client_id,
+            4,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&key),
+                signature: sign(&key, &challenge),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(reply["Response"]["result"]["Err"]["type"], "NoChallenge");
+
+        wallet_event(&bus).await;
+        assert_eq!(next(&mut client).await, None);
+    }
+
+    #[tokio::test]
+    async fn client_id_cannot_be_taken_over() {
+        let (bus, url) = start_server().await;
+        let client_id = "client-3";
+        let mut client = connect(&url, client_id).await;
+        let key = session_key();
+        let challenge = challenge(&mut client, client_id).await;
+
+        // Another connection can't replace the challenge of the client, nor get replies on its topic
+        let mut attacker = connect(&url, "attacker").await;
+        send(&mut attacker, client_id, 0, WsRequest::Challenge).await;
+        assert_eq!(next(&mut client).await, None);
+        assert_eq!(next(&mut attacker).await, None);
+
+        send(
+            &mut client,
+            client_id,
+            1,
+            WsRequest::Subscribe {
+                account: ACCOUNT.to_string(),
+                public_key: public_key(&key),
+                signature: sign(&key, &challenge),
+            },
+        )
+        .await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(
+            reply["Response"]["result"]["Ok"]["Subscribed"]["account"],
+            ACCOUNT
+        );
+
+        wallet_event(&bus).await;
+        assert!(next(&mut client).await.is_some());
+        assert_eq!(next(&mut attacker).await, None);
+    }
+
+    #[tokio::test]
+    async fn submitted_transactions_require_a_session_key_signature() {
+        let (_bus, url) = start_server().await;
+        let client_id = "client-5";
+        let mut client = connect(&url, client_id).await;
+        let key = session_key();
+        let submit = |signature: String| WsRequest::SubmitTx {
+            account: ACCOUNT.to_string(),
+            public_key: public_key(&key),
+            signature,
+            nonce: 42,
+            blobs: vec![],
+        };
+
+        // Signed for another nonce
+        send(&mut client, client_id, 1, submit(sign(&key, "41"))).await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(
+            reply["Response"]["result"]["Err"]["type"],
+            "InvalidSignature"
+        );
+
+        // Relayed to the node, which is unreachable
+        send(&mut client, client_id, 2, submit(sign(&key, "42"))).await;
+        let reply = next(&mut client).await.expect("No reply");
+        assert_eq!(reply["Response"]["result"]["Err"]["type"], "TxRejected");
+    }
+
+    #[test]
+    fn challenges_expire() {
+        let mut subscriptions = Subscriptions::default();
+        let now = Instant::now();
+        assert!(subscriptions.connect("addr", "client", now));
+
+        subscriptions.challenge("addr", now);
+        assert_eq!(
+            subscriptions.take_challenge("addr", now + CHALLENGE_TTL),
+            None
+        );
+
+        let challenge = subscriptions.challenge("addr", now);
+        assert_eq!(
+            subscriptions.take_challenge("addr", now + Duration::from_secs(1)),
+            Some(challenge)
+        );
+        // A challenge is only answered once
+        assert_eq!(subscriptions.take_challenge("addr", now), None);
+        // Challenges are only issued to known connections
+        subscriptions.challenge("other", now);
+        assert_eq!(subscriptions.take_challenge("other", now), None);
+    }
+
+    #[test]
+    fn idle_connections_are_forgotten() {
+        let mut subscriptions = Subscriptions::default();
+        let now = Instant::now();
+        let identity = format!("{ACCOUNT}@wallet");
+        assert!(subscriptions.connect("addr-1", "client-1", now));
+        assert!(subscriptions.subscribe("addr-1", identity.clone()));
+        assert!(subscriptions.connect("addr-2", "client-2", now));
+        assert!(subscriptions.subscribe("addr-2", identity.clone()));
+        // Bound to their connection
+        assert!(!subscriptions.connect("addr-3", "client-1", now));
+        assert!(!subscriptions.connect("addr-1", "client-2", now));
+
+        // Only the connection that kept pinging stays subscribed
+        let later = now + CONNECTION_TTL / 2;
+        assert!(subscriptions.connect("addr-2", "client-2", later));
+        subscriptions.prune(now + CONNECTION_TTL);
+        assert_eq!(subscriptions.subscribers(&identity), vec!["client-2"]);
+
+        // The client id of a forgotten connection can be used again
+        assert!(subscriptions.connect("addr-3", "client-1", now + CONNECTION_TTL));
+
+        subscriptions.prune(later + CONNECTION_TTL);
+        assert!(subscriptions.subscribers(&identity).is_empty());
+        assert!(subscriptions.connections.keys().eq(["addr-3"]));
+        assert!(subscriptions.subscribers.is_empty());
+        assert!(subscriptions.client_ids.keys().eq(["client-1"]));
+    }
+}